@@ -6,17 +6,476 @@ extern crate aheui;
 
 pub fn main() {
     let args = std::os::args();
+    let args = args.slice_from(1);
 
-    if args.len() <= 1 {
+    if args.len() > 0 && args[0].as_slice() == "build" {
+        build(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "coverage" {
+        coverage(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "lint" {
+        lint(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "cfg" {
+        cfg(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "explore" {
+        explore(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "storages" {
+        storages(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "strip" {
+        strip(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "stats" {
+        stats(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "disasm" {
+        disasm(args.slice_from(1));
+    } else if args.len() > 0 && args[0].as_slice() == "dap" {
+        let requests = aheui::dap::spawn_reader(std::io::stdin());
+        let mut server = aheui::dap::Server::new(box std::io::stdout());
+        match server.serve(requests) {
+            Ok(()) => { }
+            Err(e) => { printerr!("error: {}", e); }
+        }
+    } else {
+        run(args);
+    }
+}
+
+fn read_source(path_str: &str) -> aheui::Source {
+    let path = Path::new(path_str);
+    let mut file = std::io::File::open(&path).ok().expect("error: no such file");
+    aheui::Source::from_str(file.read_to_str().ok().expect("error: io error").as_slice())
+}
+
+fn run(args: &[String]) {
+    let mut jit = false;
+    let mut debug = false;
+    let mut visualize = false;
+    let mut delay = 200u64;
+    let mut size = (20u, 30u);
+    let mut profile = false;
+    let mut detect_loops = false;
+    let mut profile_csv = None;
+    let mut input = None;
+    let mut trace = None;
+    let mut trace_format = aheui::trace::JsonTrace;
+    let mut path_str = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_slice() {
+            "--jit" => { jit = true; }
+            "--debug" => { debug = true; }
+            "--visualize" => { visualize = true; }
+            "--profile" => { profile = true; }
+            "--detect-loops" => { detect_loops = true; }
+            "--profile-csv" if i + 1 < args.len() => {
+                profile_csv = Some(Path::new(args[i + 1].as_slice()));
+                i += 1;
+            }
+            "--delay" if i + 1 < args.len() => {
+                delay = match from_str(args[i + 1].as_slice()) {
+                    Some(delay) => delay,
+                    None => {
+                        printerr!("error: --delay takes milliseconds");
+                        return;
+                    }
+                };
+                i += 1;
+            }
+            "--size" if i + 1 < args.len() => {
+                let dimensions: Vec<Option<uint>> = args[i + 1].as_slice().split('x').map(|n| from_str(n)).collect();
+                size = match dimensions.as_slice() {
+                    [Some(rows), Some(cols)] if rows > 0 && cols > 0 => (rows, cols),
+                    _ => {
+                        printerr!("error: --size takes ROWSxCOLS");
+                        return;
+                    }
+                };
+                i += 1;
+            }
+            "--input" if i + 1 < args.len() => {
+                input = Some(Path::new(args[i + 1].as_slice()));
+                i += 1;
+            }
+            "--trace" if i + 1 < args.len() => {
+                trace = Some(Path::new(args[i + 1].as_slice()));
+                i += 1;
+            }
+            "--trace-format" if i + 1 < args.len() => {
+                trace_format = match args[i + 1].as_slice() {
+                    "json" => aheui::trace::JsonTrace,
+                    "text" => aheui::trace::TextTrace,
+                    format => {
+                        printerr!("error: unknown trace format: {}", format);
+                        return;
+                    }
+                };
+                i += 1;
+            }
+            _ => { path_str = Some(args[i].clone()); }
+        }
+        i += 1;
+    }
+
+    let path_str = match path_str {
+        Some(path_str) => path_str,
+        None => {
+            printerr!("error: no input files");
+            return;
+        }
+    };
+
+    let source = read_source(path_str.as_slice());
+    if visualize {
+        let reader: Box<Buffer> = match input {
+            Some(path) => box std::io::BufferedReader::new(std::io::File::open(&path).ok().expect("error: no such input file")),
+            None => box std::io::stdin(),
+        };
+        let (rows, cols) = size;
+        let mut visualizer = aheui::visualize::Visualizer::new(source, reader, rows, cols, delay);
+        let keys = match aheui::visualize::Keys::open() {
+            Ok(keys) => keys,
+            Err(e) => {
+                printerr!("error: cannot read keys from the terminal: {}", e);
+                return;
+            }
+        };
+        let _ = visualizer.run(keys.receiver(), &mut std::io::stdout());
+        return;
+    }
+    // The debugger reads its commands from stdin, so the program gets no
+    // input unless a file is given.
+    let reader: Box<Buffer> = match input {
+        Some(path) => box std::io::BufferedReader::new(std::io::File::open(&path).ok().expect("error: no such input file")),
+        None if debug && aheui::graph::Graph::from_source(&source).reads_input() => {
+            printerr!("error: the program reads input; give it with --input FILE when debugging");
+            std::os::set_exit_status(1);
+            return;
+        }
+        None if debug => box std::io::MemReader::new(Vec::new()),
+        None => box std::io::stdin(),
+    };
+    let mut interpreter = aheui::Interpreter::with_io(source, reader, box std::io::stdout());
+    match trace {
+        Some(path) => {
+            let file = std::io::File::create(&path).ok().expect("error: cannot create trace file");
+            interpreter.set_trace(box std::io::BufferedWriter::new(file), trace_format);
+            if jit {
+                printerr!("warning: --trace is ignored with --jit");
+                jit = false;
+            }
+        }
+        None => { }
+    }
+    if profile || profile_csv.is_some() {
+        interpreter.enable_profile();
+        if jit {
+            printerr!("warning: --profile is ignored with --jit");
+            jit = false;
+        }
+    }
+    if detect_loops && jit {
+        printerr!("warning: --detect-loops is ignored with --jit");
+        jit = false;
+    }
+    if debug {
+        let mut debugger = aheui::debugger::Debugger::new(interpreter);
+        let _ = debugger.run(&mut std::io::stdin(), &mut std::io::stdout());
+        return;
+    }
+    if jit {
+        execute_jit(&mut interpreter);
+    } else if detect_loops {
+        interpreter.enable_loop_detection();
+        match interpreter.run() {
+            aheui::Stopped(aheui::InfiniteLoop(found)) => {
+                interpreter.flush();
+                printerr!("error: infinite loop {}", found.describe());
+                std::os::set_exit_status(1);
+                return;
+            }
+            _ => { }
+        }
+    } else {
+        interpreter.execute();
+    }
+    interpreter.flush();
+
+    match interpreter.profile() {
+        Some(report) => {
+            let mut err = std::io::stderr();
+            if profile {
+                let _ = report.write_report(interpreter.source(), &mut err, 10);
+                let _ = report.write_heat_map(interpreter.source(), &mut err);
+            }
+            match profile_csv {
+                Some(path) => {
+                    let result = std::io::File::create(&path).and_then(|mut file| report.write_csv(interpreter.source(), &mut file));
+                    match result {
+                        Ok(()) => { }
+                        Err(e) => { printerr!("error: cannot write {}: {}", path.display(), e); }
+                    }
+                }
+                None => { }
+            }
+        }
+        None => { }
+    }
+}
+
+/// `aheui build prog.aheui [-o prog]` writes a standalone executable.
+fn build(args: &[String]) {
+    let mut path_str = None;
+    let mut output = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_slice() {
+            "-o" if i + 1 < args.len() => {
+                output = Some(Path::new(args[i + 1].as_slice()));
+                i += 1;
+            }
+            _ => { path_str = Some(args[i].clone()); }
+        }
+        i += 1;
+    }
+
+    let path_str = match path_str {
+        Some(path_str) => path_str,
+        None => {
+            printerr!("error: no input files");
+            return;
+        }
+    };
+    let output = match output {
+        Some(output) => output,
+        None => Path::new(path_str.as_slice()).with_extension(""),
+    };
+    if output == Path::new(path_str.as_slice()) {
+        printerr!("error: the executable would overwrite {}; name it with -o", path_str);
+        std::os::set_exit_status(1);
+        return;
+    }
+
+    let source = read_source(path_str.as_slice());
+    match aheui::elf::write_executable(&source, &output) {
+        Ok(()) => { }
+        Err(e) => { printerr!("error: {}", e); }
+    }
+}
+
+/// `aheui coverage prog.aheui [INPUT...]` runs the program once per input
+/// file, or once without input if it reads none, and reports what the runs
+/// executed together.
+fn coverage(args: &[String]) {
+    let path_str = match args.get(0) {
+        Some(path_str) => path_str,
+        None => {
+            printerr!("error: no input files");
+            return;
+        }
+    };
+    let source = read_source(path_str.as_slice());
+    let mut readers: Vec<Box<Buffer>> = Vec::new();
+    for input in args.slice_from(1).iter() {
+        match std::io::File::open(&Path::new(input.as_slice())) {
+            Ok(file) => { readers.push(box std::io::BufferedReader::new(file) as Box<Buffer>); }
+            Err(e) => {
+                printerr!("error: cannot open {}: {}", input, e);
+                return;
+            }
+        }
+    }
+    if readers.is_empty() {
+        if aheui::graph::Graph::from_source(&source).reads_input() {
+            printerr!("error: the program reads input; give at least one input file");
+            std::os::set_exit_status(1);
+            return;
+        }
+        readers.push(box std::io::MemReader::new(Vec::new()) as Box<Buffer>);
+    }
+
+    let mut total = aheui::coverage::Coverage::new();
+    for reader in readers.move_iter() {
+        let mut interpreter = aheui::Interpreter::with_io(source.clone(), reader, box std::io::util::NullWriter);
+        interpreter.enable_coverage();
+        interpreter.execute();
+        total.merge(interpreter.coverage().unwrap());
+    }
+    let _ = total.write_report(&source, &mut std::io::stdout());
+}
+
+/// `aheui lint [--may-underflow] prog.aheui...` prints warnings and fails if
+/// there are any. `--may-underflow` also warns about syllables which
+/// underflow on some paths only.
+fn lint(args: &[String]) {
+    let may_underflow = args.len() > 0 && args[0].as_slice() == "--may-underflow";
+    let args = if may_underflow { args.slice_from(1) } else { args };
+    if args.is_empty() {
         printerr!("error: no input files");
         return;
     }
+    let mut warned = false;
+    for path_str in args.iter() {
+        let source = read_source(path_str.as_slice());
+        for warning in aheui::lint::lint(&source, may_underflow).iter() {
+            let _ = warning.write(path_str.as_slice(), &mut std::io::stdout());
+            warned = true;
+        }
+    }
+    if warned {
+        std::os::set_exit_status(1);
+    }
+}
 
-    let path_str = &args.as_slice()[1];
-    let path = Path::new(path_str.as_slice());
+/// `aheui cfg prog.aheui` prints the control flow of the program as DOT.
+fn cfg(args: &[String]) {
+    if args.len() != 1 {
+        printerr!("error: usage: aheui cfg FILE");
+        return;
+    }
+    let source = read_source(args[0].as_slice());
+    let flow = aheui::cfg::ControlFlow::from_source(&source);
+    match flow.write_dot(&mut std::io::stdout()) {
+        Ok(()) => { }
+        Err(e) => { printerr!("error: {}", e); }
+    }
+}
 
-    let mut file = std::io::File::open(&path).ok().expect("error: no such file");
-    let source = aheui::Source::from_str(file.read_to_str().ok().expect("error: io error").as_slice());
-    let mut interpreter = aheui::Interpreter::new(source);
+/// `aheui explore prog.aheui` prints inputs reaching each branch outcome
+/// and halt of the program.
+fn explore(args: &[String]) {
+    if args.len() != 1 {
+        printerr!("error: usage: aheui explore FILE");
+        return;
+    }
+    let path_str = args[0].as_slice();
+    let source = read_source(path_str);
+    let exploration = aheui::symbolic::explore(&source, aheui::symbolic::DEFAULT_PATHS,
+                                               aheui::symbolic::DEFAULT_STEPS);
+    for witness in exploration.witnesses.iter() {
+        let ((row, col), target) = match witness.target {
+            aheui::symbolic::BranchTarget(position, true) => (position, "branch taken on nonzero"),
+            aheui::symbolic::BranchTarget(position, false) => (position, "branch taken on zero"),
+            aheui::symbolic::HaltTarget(position) => (position, "halt"),
+        };
+        println!("{}:{}:{}: {} with input \"{}\"{}", path_str, row + 1, col + 1, target,
+                 witness.input_text().as_slice().escape_default(),
+                 if witness.exact { "" } else { " (inexact)" });
+    }
+    if !exploration.complete {
+        printerr!("warning: {} paths explored, not all to the end; other targets may be reachable", exploration.paths);
+    }
+}
+
+/// `aheui storages prog.aheui` prints where each storage is used and the
+/// moves between them.
+fn storages(args: &[String]) {
+    if args.len() != 1 {
+        printerr!("error: usage: aheui storages FILE");
+        return;
+    }
+    let source = read_source(args[0].as_slice());
+    let usage = aheui::usage::Usage::from_source(&source);
+    match usage.write_report(&source, &mut std::io::stdout()) {
+        Ok(()) => { }
+        Err(e) => { printerr!("error: {}", e); }
+    }
+}
+
+/// `aheui strip prog.aheui [--input FILE] [-o OUT]` writes the program
+/// without its unreachable cells, once a run on the input shows the same
+/// output. The input is required if the program reads any.
+fn strip(args: &[String]) {
+    let mut path_str = None;
+    let mut input = None;
+    let mut output = None;
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_slice() {
+            "--input" if i + 1 < args.len() => {
+                input = Some(Path::new(args[i + 1].as_slice()));
+                i += 1;
+            }
+            "-o" if i + 1 < args.len() => {
+                output = Some(Path::new(args[i + 1].as_slice()));
+                i += 1;
+            }
+            _ => { path_str = Some(args[i].clone()); }
+        }
+        i += 1;
+    }
+    let path_str = match path_str {
+        Some(path_str) => path_str,
+        None => {
+            printerr!("error: usage: aheui strip FILE [--input FILE] [-o OUT]");
+            return;
+        }
+    };
+    let original = std::io::File::open(&Path::new(path_str.as_slice())).read_to_str().ok().expect("error: no such file");
+    let source = aheui::Source::from_str(original.as_slice());
+    let input = match input {
+        Some(path) => std::io::File::open(&path).read_to_end().ok().expect("error: no such input file"),
+        None if aheui::graph::Graph::from_source(&source).reads_input() => {
+            printerr!("error: the program reads input; give it with --input FILE");
+            std::os::set_exit_status(1);
+            return;
+        }
+        None => Vec::new(),
+    };
+
+    let stripped = aheui::strip::strip(&source);
+    match aheui::strip::verify(original.as_slice(), stripped.text.as_slice(), input.as_slice(),
+                               aheui::strip::DEFAULT_STEPS) {
+        Ok(()) => { }
+        Err(e) => {
+            printerr!("error: {}", e);
+            std::os::set_exit_status(1);
+            return;
+        }
+    }
+    let result = match output {
+        Some(path) => std::io::File::create(&path).write_str(stripped.text.as_slice()),
+        None => std::io::stdout().write_str(stripped.text.as_slice()),
+    };
+    match result {
+        Ok(()) => { }
+        Err(e) => { printerr!("error: {}", e); }
+    }
+    printerr!("blanked {} syllables, trimmed {} cells and {} rows", stripped.blanked, stripped.trimmed_cells,
+              stripped.trimmed_rows);
+}
+
+/// `aheui stats prog.aheui` prints the size and composition of the program.
+fn stats(args: &[String]) {
+    if args.len() != 1 {
+        printerr!("error: usage: aheui stats FILE");
+        return;
+    }
+    let source = read_source(args[0].as_slice());
+    match aheui::stats::Stats::from_source(&source).write(&mut std::io::stdout()) {
+        Ok(()) => { }
+        Err(e) => { printerr!("error: {}", e); }
+    }
+}
+
+/// `aheui disasm prog.aheui` lists the reachable syllables along the paths
+/// through the program, with labels and jumps.
+fn disasm(args: &[String]) {
+    if args.len() != 1 {
+        printerr!("error: usage: aheui disasm FILE");
+        return;
+    }
+    let source = read_source(args[0].as_slice());
+    match aheui::disasm::Listing::from_source(&source).write(&mut std::io::stdout()) {
+        Ok(()) => { }
+        Err(e) => { printerr!("error: {}", e); }
+    }
+}
+
+#[cfg(target_os = "linux", target_arch = "x86_64")]
+fn execute_jit(interpreter: &mut aheui::Interpreter) {
+    aheui::jit::execute(interpreter);
+}
+
+#[cfg(not(target_os = "linux", target_arch = "x86_64"))]
+fn execute_jit(interpreter: &mut aheui::Interpreter) {
+    printerr!("warning: --jit is only available on x86-64 Linux");
     interpreter.execute();
-}
\ No newline at end of file
+}
@@ -0,0 +1,222 @@
+//! Control flow of a program as straight-line runs of syllables, for drawing
+//! with Graphviz.
+//!
+//! A run ends at a decision point: a branch, an operation which may reflect,
+//! an input or a halt. It also ends before a syllable reached from several
+//! places. Operations which never underflow according to `DepthAnalysis` are
+//! not decision points, and the regular edge of those which always do is
+//! left out. Walls and characters other than syllables are passed through.
+
+use std::collections::{HashMap, HashSet};
+use std::io::IoResult;
+
+use super::{Source, HaltOperation, BranchOperation, PushIntegerInputOperation, PushCharInputOperation};
+use graph::{Graph, Goto};
+use depth::{DepthAnalysis, Never, Always};
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Outcome {
+    Fallthrough,
+    Nonzero,
+    Zero,
+    /// The storage held enough values.
+    Enough,
+    Underflow,
+}
+
+impl Outcome {
+    pub fn name(&self) -> Option<&'static str> {
+        match *self {
+            Fallthrough => None,
+            Nonzero => Some("nonzero"),
+            Zero => Some("zero"),
+            Enough => Some("enough"),
+            Underflow => Some("underflow"),
+        }
+    }
+}
+
+pub struct Exit {
+    /// Index of the block the edge goes to.
+    pub target: uint,
+    pub outcome: Outcome,
+}
+
+pub struct Block {
+    /// Nodes of the `Graph` executed in order; all of them are syllables.
+    pub nodes: Vec<uint>,
+    pub exits: Vec<Exit>,
+}
+
+pub struct ControlFlow {
+    pub graph: Graph,
+    /// Runs in discovery order; the first one is executed first.
+    pub blocks: Vec<Block>,
+}
+
+impl ControlFlow {
+    pub fn from_source(source: &Source) -> ControlFlow {
+        let graph = Graph::from_source(source);
+        let depth = DepthAnalysis::from_graph(&graph);
+
+        let mut edges: Vec<Vec<(uint, Outcome)>> = Vec::new();
+        for idx in range(0, graph.len()) {
+            let mut resolved = Vec::new();
+            for &(target, outcome) in raw_edges(&graph, &depth, idx).iter() {
+                match resolve(&graph, target) {
+                    Some(target) => { resolved.push((target, outcome)); }
+                    None => { }
+                }
+            }
+            edges.push(resolved);
+        }
+
+        let mut blocks = Vec::new();
+        let start = match resolve(&graph, 0) {
+            Some(start) => start,
+            None => { return ControlFlow { graph: graph, blocks: blocks }; }
+        };
+
+        // Discover the syllables actually reachable. A run starts at the
+        // start, after a decision point and where several edges enter.
+        let mut entries: HashMap<uint, uint> = HashMap::new();
+        let mut leaders: HashSet<uint> = HashSet::new();
+        leaders.insert(start);
+        let mut order = vec!(start);
+        let mut i = 0;
+        while i < order.len() {
+            let idx = *order.get(i);
+            let decision = decides(&graph, edges.get(idx).as_slice(), idx);
+            for &(target, _) in edges.get(idx).iter() {
+                if !entries.contains_key(&target) && target != start {
+                    order.push(target);
+                }
+                entries.insert_or_update_with(target, 1, |_, count| *count += 1);
+                if decision || *entries.get(&target) > 1 {
+                    leaders.insert(target);
+                }
+            }
+            i += 1;
+        }
+
+        let mut block_of: HashMap<uint, uint> = HashMap::new();
+        for idx in order.iter().filter(|idx| leaders.contains(*idx)) {
+            let next = block_of.len();
+            block_of.insert(*idx, next);
+        }
+        for idx in order.iter().filter(|idx| leaders.contains(*idx)) {
+            let mut nodes = vec!(*idx);
+            loop {
+                let last = *nodes.last().unwrap();
+                let out = edges.get(last);
+                if decides(&graph, out.as_slice(), last) {
+                    break;
+                }
+                let (next, _) = *out.get(0);
+                if leaders.contains(&next) {
+                    break;
+                }
+                nodes.push(next);
+            }
+            let exits = edges.get(*nodes.last().unwrap()).iter().map(|&(target, outcome)| {
+                Exit { target: *block_of.get(&target), outcome: outcome }
+            }).collect();
+            blocks.push(Block { nodes: nodes, exits: exits });
+        }
+        ControlFlow { graph: graph, blocks: blocks }
+    }
+
+    /// Writes the flow as a DOT digraph. Blocks are labelled by the
+    /// positions of their first and last syllables and the syllables
+    /// themselves; edges by the direction taken and the outcome.
+    pub fn write_dot(&self, out: &mut Writer) -> IoResult<()> {
+        try!(writeln!(out, "digraph aheui {{"));
+        try!(writeln!(out, "    node [shape=box, fontname=\"monospace\"];"));
+        try!(writeln!(out, "    start [shape=point];"));
+        for (i, block) in self.blocks.iter().enumerate() {
+            let first = self.graph.nodes.get(*block.nodes.get(0));
+            let last = self.graph.nodes.get(*block.nodes.last().unwrap());
+            let (row, col) = first.state.position();
+            let mut label = format!("({},{})", row, col);
+            if block.nodes.len() > 1 {
+                let (row, col) = last.state.position();
+                label.push_str(format!("..({},{})", row, col).as_slice());
+            }
+            label.push_str("\\n");
+            for idx in block.nodes.iter() {
+                label.push_char(self.graph.nodes.get(*idx).instruction.char().unwrap());
+            }
+            let halts = match last.instruction.operation() {
+                HaltOperation => ", peripheries=2",
+                _ => "",
+            };
+            try!(writeln!(out, "    b{} [label=\"{}\"{}];", i, label, halts));
+        }
+        if !self.blocks.is_empty() {
+            try!(writeln!(out, "    start -> b0;"));
+        }
+        for (i, block) in self.blocks.iter().enumerate() {
+            for exit in block.exits.iter() {
+                let first = self.blocks.get(exit.target).nodes.get(0);
+                let direction = self.graph.nodes.get(*first).state.direction.name();
+                let label = match exit.outcome.name() {
+                    Some(outcome) => format!("{}, {}", direction, outcome),
+                    None => String::from_str(direction),
+                };
+                try!(writeln!(out, "    b{} -> b{} [label=\"{}\"];", i, exit.target, label));
+            }
+        }
+        writeln!(out, "}}")
+    }
+}
+
+/// Edges of a node which the storages may take.
+fn raw_edges(graph: &Graph, depth: &DepthAnalysis, idx: uint) -> Vec<(uint, Outcome)> {
+    let node = graph.nodes.get(idx);
+    let operation = node.instruction.operation();
+    let mut edges = Vec::new();
+    let (regular, reflected) = match operation {
+        HaltOperation => { return edges; }
+        BranchOperation => (Some(Nonzero), Some(Zero)),
+        _ if !operation.can_reflect() => (Some(Fallthrough), None),
+        _ => match depth.underflow(node.state.position()) {
+            Some(Never) => (Some(Fallthrough), None),
+            Some(Always) => (None, Some(Underflow)),
+            _ => (Some(Enough), Some(Underflow)),
+        },
+    };
+    match (regular, node.next) {
+        (Some(outcome), Goto(next)) => { edges.push((next, outcome)); }
+        _ => { }
+    }
+    match (reflected, node.reflected) {
+        (Some(outcome), Some(Goto(next))) => { edges.push((next, outcome)); }
+        _ => { }
+    }
+    edges
+}
+
+/// First syllable executed from a node on, passing walls and other
+/// characters. None if the counter escapes or never meets a syllable.
+fn resolve(graph: &Graph, idx: uint) -> Option<uint> {
+    let mut idx = idx;
+    for _ in range(0, graph.len()) {
+        let node = graph.nodes.get(idx);
+        if node.instruction.is_hangeul() {
+            return Some(idx);
+        }
+        idx = match node.next {
+            Goto(next) => next,
+            _ => { return None; }
+        };
+    }
+    None
+}
+
+/// Whether a run of syllables has to end after a node.
+fn decides(graph: &Graph, edges: &[(uint, Outcome)], idx: uint) -> bool {
+    match graph.nodes.get(idx).instruction.operation() {
+        HaltOperation | PushIntegerInputOperation | PushCharInputOperation => true,
+        _ => edges.len() != 1 || edges.iter().any(|&(_, outcome)| outcome != Fallthrough),
+    }
+}
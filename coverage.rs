@@ -0,0 +1,179 @@
+//! Which parts of a program some runs executed.
+//!
+//! Three things are covered: the cells holding a syllable, the two outcomes
+//! of every branch (ㅊ), and the other operations which reverse the movement
+//! when their storage lacks values. Coverages of runs with different inputs
+//! are combined with `merge`.
+//!
+//! The report prints the program with a line under each row that marks
+//!
+//! ```text
+//! ^^  a syllable never executed
+//! ~~  a branch executed with only one of its outcomes
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::io::IoResult;
+
+use super::{Source, BranchOperation};
+use visualize::width;
+
+#[deriving(Clone)]
+pub struct Coverage {
+    visited: HashSet<(int, int)>,
+    /// Whether each branch went on and whether it reversed the movement.
+    branches: HashMap<(int, int), (bool, bool)>,
+    /// Cells other than branches whose operation reversed the movement.
+    reflections: HashSet<(int, int)>,
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            visited: HashSet::new(),
+            branches: HashMap::new(),
+            reflections: HashSet::new(),
+        }
+    }
+
+    /// Records the execution of the instruction at `position`, in program
+    /// coordinates.
+    pub fn record(&mut self, source: &Source, position: (int, int), reflected: bool) {
+        self.visited.insert(position);
+        match source.get(position).operation() {
+            BranchOperation => {
+                let outcome = self.branches.find_or_insert(position, (false, false));
+                let (on, reversed) = *outcome;
+                *outcome = if reflected { (on, true) } else { (true, reversed) };
+            }
+            _ if reflected => { self.reflections.insert(position); }
+            _ => { }
+        }
+    }
+
+    /// Adds what `other` covered.
+    pub fn merge(&mut self, other: &Coverage) {
+        for position in other.visited.iter() {
+            self.visited.insert(*position);
+        }
+        for (position, &(on, reversed)) in other.branches.iter() {
+            let outcome = self.branches.find_or_insert(*position, (false, false));
+            let (mine_on, mine_reversed) = *outcome;
+            *outcome = (mine_on || on, mine_reversed || reversed);
+        }
+        for position in other.reflections.iter() {
+            self.reflections.insert(*position);
+        }
+    }
+
+    pub fn visited(&self, position: (int, int)) -> bool {
+        self.visited.contains(&position)
+    }
+
+    /// Outcomes of the branch at `position`: whether it went on and whether
+    /// it reversed the movement.
+    pub fn branch(&self, position: (int, int)) -> (bool, bool) {
+        *self.branches.find(&position).unwrap_or(&(false, false))
+    }
+
+    pub fn reflected(&self, position: (int, int)) -> bool {
+        self.reflections.contains(&position)
+    }
+
+    /// Covered and total counts of cells, branch outcomes and reflection
+    /// points of `source`.
+    pub fn summary(&self, source: &Source) -> Summary {
+        let mut summary = Summary {
+            cells: (0, 0),
+            branch_outcomes: (0, 0),
+            reflection_points: (0, 0),
+        };
+        for position in cells(source).iter() {
+            let operation = source.get(*position).operation();
+            let (covered, total) = summary.cells;
+            summary.cells = (covered + if self.visited(*position) { 1 } else { 0 }, total + 1);
+            match operation {
+                BranchOperation => {
+                    let (on, reversed) = self.branch(*position);
+                    let (covered, total) = summary.branch_outcomes;
+                    summary.branch_outcomes = (covered + on as uint + reversed as uint, total + 2);
+                }
+                operation if operation.can_reflect() => {
+                    let (covered, total) = summary.reflection_points;
+                    summary.reflection_points = (covered + if self.reflected(*position) { 1 } else { 0 }, total + 1);
+                }
+                _ => { }
+            }
+        }
+        summary
+    }
+
+    /// Writes the marked program followed by the summary.
+    pub fn write_report(&self, source: &Source, out: &mut Writer) -> IoResult<()> {
+        for row in range(0, source.height()) {
+            let mut marks = String::new();
+            let mut marked = false;
+            try!(write!(out, "{:4} ", row));
+            for col in range(0, source.row_len(row)) {
+                let position = (row as int, col as int);
+                let instruction = source.get(position);
+                let c = match instruction.char() {
+                    Some(c) if !c.is_control() => c,
+                    _ => ' ',
+                };
+                try!(write!(out, "{}", c));
+                let mark = match instruction.operation() {
+                    _ if !instruction.is_hangeul() => ' ',
+                    _ if !self.visited(position) => '^',
+                    BranchOperation if self.branch(position) != (true, true) => '~',
+                    _ => ' ',
+                };
+                marked = marked || mark != ' ';
+                marks.grow(width(c), mark);
+            }
+            try!(writeln!(out, ""));
+            if marked {
+                try!(writeln!(out, "     {}", marks.as_slice().trim_right()));
+            }
+        }
+        self.summary(source).write(out)
+    }
+}
+
+/// Positions of the syllables of a program.
+fn cells(source: &Source) -> Vec<(int, int)> {
+    let mut cells = Vec::new();
+    for row in range(0, source.height()) {
+        for col in range(0, source.row_len(row)) {
+            let position = (row as int, col as int);
+            if source.get(position).is_hangeul() {
+                cells.push(position);
+            }
+        }
+    }
+    cells
+}
+
+/// Pairs of covered and total counts.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Summary {
+    pub cells: (uint, uint),
+    pub branch_outcomes: (uint, uint),
+    pub reflection_points: (uint, uint),
+}
+
+impl Summary {
+    pub fn write(&self, out: &mut Writer) -> IoResult<()> {
+        try!(write_ratio(out, "cells", self.cells));
+        try!(write_ratio(out, "branch outcomes", self.branch_outcomes));
+        write_ratio(out, "reflection points", self.reflection_points)
+    }
+}
+
+fn write_ratio(out: &mut Writer, name: &str, (covered, total): (uint, uint)) -> IoResult<()> {
+    if total == 0 {
+        writeln!(out, "{}: none", name)
+    } else {
+        writeln!(out, "{}: {}/{} ({:.1}%)", name, covered, total, 100.0 * covered as f64 / total as f64)
+    }
+}
@@ -0,0 +1,525 @@
+//! Debug Adapter Protocol server, for debugging programs in editors.
+//!
+//! Messages are JSON bodies preceded by a `Content-Length` header. Lines and
+//! columns start at 1 and a column is a cell of the grid. A breakpoint without
+//! a column is put on the first syllable of its line. The program has a
+//! single thread and a single stack frame, named after the syllable under the
+//! counter. The storages and the rest of the state are shown as variables.
+//!
+//! `launch` takes the path of the `program`, the path of an `input` file and
+//! optionally `stopOnEntry`. The input may be left out if the program never
+//! reads it. The program runs in a task of its own at each request, so that
+//! its failing ends the session with exit code 1 rather than the adapter.
+
+use std::any::AnyRefExt;
+use std::comm::{Empty, Disconnected};
+use std::io::{File, IoResult, BufferedReader, MemReader, ChanWriter};
+use std::io::util::NullWriter;
+use std::task::TaskBuilder;
+use serialize::json;
+use serialize::json::Json;
+
+use super::{Interpreter, Source, CellBreakpoint, storage_names};
+use graph::Graph;
+use super::{RunResult, Halted, Stopped, BreakpointHit, WatchpointHit, InfiniteLoop};
+use trace::quote;
+
+static THREAD_ID: uint = 1;
+static FRAME_ID: uint = 1;
+static STORAGES_REFERENCE: uint = 1;
+static STATE_REFERENCE: uint = 2;
+/// Steps executed between two looks at incoming requests while running.
+static BATCH: uint = 10000;
+
+/// Reads one message, without its header.
+pub fn read_message(input: &mut Buffer) -> IoResult<String> {
+    let mut length = 0u;
+    loop {
+        let line = try!(input.read_line());
+        let line = line.as_slice().trim();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(':', 1);
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value)) if name.trim() == "Content-Length" => {
+                length = from_str(value.trim()).unwrap_or(0);
+            }
+            _ => { }
+        }
+    }
+    let body = try!(input.read_exact(length));
+    Ok(String::from_utf8(body).ok().unwrap_or(String::new()))
+}
+
+pub fn write_message(out: &mut Writer, body: &str) -> IoResult<()> {
+    try!(write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body));
+    out.flush()
+}
+
+/// Starts a task reading messages from `input` until it ends.
+pub fn spawn_reader<R: Buffer + Send>(input: R) -> Receiver<String> {
+    let (tx, rx) = channel();
+    spawn(proc() {
+        let mut input = input;
+        loop {
+            match read_message(&mut input) {
+                Ok(message) => {
+                    if tx.send_opt(message).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => { break; }
+            }
+        }
+    });
+    rx
+}
+
+pub struct Server {
+    out: Box<Writer>,
+    seq: uint,
+    path: String,
+    interpreter: Option<Interpreter>,
+    output: Option<Receiver<Vec<u8>>>,
+    /// Ids and cells, in program coordinates, of the verified breakpoints
+    /// in the order the interpreter numbers them.
+    breakpoints: Vec<(uint, (int, int))>,
+    /// Id of the last breakpoint set.
+    breakpoint_id: uint,
+    stop_on_entry: bool,
+    running: bool,
+    /// Set once the program halts; it is not run any further.
+    halted: bool,
+    finished: bool,
+}
+
+impl Server {
+    pub fn new(out: Box<Writer>) -> Server {
+        Server {
+            out: out,
+            seq: 0,
+            path: String::new(),
+            interpreter: None,
+            output: None,
+            breakpoints: Vec::new(),
+            breakpoint_id: 0,
+            stop_on_entry: false,
+            running: false,
+            halted: false,
+            finished: false,
+        }
+    }
+
+    /// Serves requests until `disconnect` or the end of `requests`.
+    pub fn serve(&mut self, requests: Receiver<String>) -> IoResult<()> {
+        while !self.finished {
+            if self.running {
+                match requests.try_recv() {
+                    Ok(request) => { try!(self.handle(request.as_slice())); }
+                    Err(Empty) => { }
+                    Err(Disconnected) => { return Ok(()); }
+                }
+                if self.running {
+                    try!(self.advance());
+                }
+            } else {
+                match requests.recv_opt() {
+                    Ok(request) => { try!(self.handle(request.as_slice())); }
+                    Err(()) => { return Ok(()); }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles one request.
+    pub fn handle(&mut self, message: &str) -> IoResult<()> {
+        let request = match json::from_str(message) {
+            Ok(request) => request,
+            Err(_) => { return Ok(()); }
+        };
+        let request_seq = number(&request, "seq").unwrap_or(0);
+        let command = match string(&request, "command") {
+            Some(command) => String::from_str(command),
+            None => { return Ok(()); }
+        };
+        let arguments = field(&request, "arguments");
+        let command = command.as_slice();
+        match command {
+            "initialize" => {
+                try!(self.respond(request_seq, command, "{\"supportsConfigurationDoneRequest\":true}"));
+            }
+            "launch" => {
+                match self.launch(arguments) {
+                    Ok(()) => {
+                        try!(self.respond(request_seq, command, "{}"));
+                        try!(self.event("initialized", "{}"));
+                    }
+                    Err(e) => { try!(self.fail(request_seq, command, e.as_slice())); }
+                }
+            }
+            "setBreakpoints" => {
+                let body = self.set_breakpoints(arguments);
+                try!(self.respond(request_seq, command, body.as_slice()));
+            }
+            "setExceptionBreakpoints" => {
+                try!(self.respond(request_seq, command, "{}"));
+            }
+            "configurationDone" => {
+                try!(self.respond(request_seq, command, "{}"));
+                if self.stop_on_entry {
+                    try!(self.stopped("entry"));
+                } else {
+                    self.running = true;
+                }
+            }
+            "threads" => {
+                let body = format!("{{\"threads\":[{{\"id\":{},\"name\":\"main\"}}]}}", THREAD_ID);
+                try!(self.respond(request_seq, command, body.as_slice()));
+            }
+            "stackTrace" => {
+                let body = self.stack_trace();
+                try!(self.respond(request_seq, command, body.as_slice()));
+            }
+            "scopes" => {
+                let body = format!("{{\"scopes\":[\
+                                    {{\"name\":\"Storages\",\"variablesReference\":{},\"expensive\":false}},\
+                                    {{\"name\":\"State\",\"variablesReference\":{},\"expensive\":false}}]}}",
+                                   STORAGES_REFERENCE, STATE_REFERENCE);
+                try!(self.respond(request_seq, command, body.as_slice()));
+            }
+            "variables" => {
+                let reference = arguments.and_then(|arguments| number(arguments, "variablesReference")).unwrap_or(0);
+                let body = self.variables(reference as uint);
+                try!(self.respond(request_seq, command, body.as_slice()));
+            }
+            "continue" => {
+                try!(self.respond(request_seq, command, "{\"allThreadsContinued\":true}"));
+                self.running = self.interpreter.is_some() && !self.halted;
+            }
+            "next" | "stepIn" | "stepOut" => {
+                try!(self.respond(request_seq, command, "{}"));
+                self.running = false;
+                try!(self.step());
+            }
+            "pause" => {
+                try!(self.respond(request_seq, command, "{}"));
+                if self.running {
+                    self.running = false;
+                    try!(self.stopped("pause"));
+                }
+            }
+            "disconnect" | "terminate" => {
+                try!(self.respond(request_seq, command, "{}"));
+                self.finished = true;
+            }
+            _ => {
+                try!(self.fail(request_seq, command, "unsupported request"));
+            }
+        }
+        Ok(())
+    }
+
+    fn launch(&mut self, arguments: Option<&Json>) -> Result<(), String> {
+        let arguments = match arguments {
+            Some(arguments) => arguments,
+            None => { return Err(String::from_str("no launch arguments")); }
+        };
+        let path = match string(arguments, "program") {
+            Some(path) => String::from_str(path),
+            None => { return Err(String::from_str("no program to launch")); }
+        };
+        let source = match File::open(&Path::new(path.as_slice())).and_then(|mut file| file.read_to_str()) {
+            Ok(text) => Source::from_str(text.as_slice()),
+            Err(e) => { return Err(format!("cannot read {}: {}", path, e)); }
+        };
+        let input: Box<Buffer> = match string(arguments, "input") {
+            Some(input) => match File::open(&Path::new(input)) {
+                Ok(file) => box BufferedReader::new(file),
+                Err(e) => { return Err(format!("cannot open {}: {}", input, e)); }
+            },
+            None if Graph::from_source(&source).reads_input() => {
+                return Err(String::from_str("the program reads input, but no input file was given"));
+            }
+            None => box MemReader::new(Vec::new()),
+        };
+        let (tx, rx) = channel();
+        let mut interpreter = Interpreter::with_io(source, input, box ChanWriter::new(tx));
+        interpreter.enable_loop_detection();
+        for &(_, position) in self.breakpoints.iter() {
+            interpreter.add_breakpoint(CellBreakpoint(position));
+        }
+        self.path = path;
+        self.interpreter = Some(interpreter);
+        self.output = Some(rx);
+        self.halted = false;
+        self.stop_on_entry = match field(arguments, "stopOnEntry") {
+            Some(flag) => flag.as_boolean().unwrap_or(false),
+            None => false,
+        };
+        Ok(())
+    }
+
+    /// Replaces the breakpoints, returning the body of the response.
+    fn set_breakpoints(&mut self, arguments: Option<&Json>) -> String {
+        let requested = match arguments.and_then(|arguments| field(arguments, "breakpoints")).and_then(|list| list.as_list()) {
+            Some(list) => list.clone(),
+            None => Vec::new(),
+        };
+        self.breakpoints.clear();
+        let mut body = String::from_str("{\"breakpoints\":[");
+        for (idx, breakpoint) in requested.iter().enumerate() {
+            let line = number(breakpoint, "line").unwrap_or(0);
+            let cell = match number(breakpoint, "column") {
+                Some(column) => Some((line - 1, column - 1)),
+                None => self.first_syllable(line - 1),
+            };
+            if idx > 0 {
+                body.push_str(",");
+            }
+            match cell {
+                Some((row, col)) if row >= 0 && col >= 0 => {
+                    self.breakpoint_id += 1;
+                    self.breakpoints.push((self.breakpoint_id, (row, col)));
+                    body.push_str(format!("{{\"id\":{},\"verified\":true,\"line\":{},\"column\":{}}}",
+                                          self.breakpoint_id, row + 1, col + 1).as_slice());
+                }
+                _ => {
+                    body.push_str(format!("{{\"verified\":false,\"line\":{}}}", line).as_slice());
+                }
+            }
+        }
+        body.push_str("]}");
+
+        match self.interpreter {
+            Some(ref mut interpreter) => {
+                while interpreter.remove_breakpoint(0).is_some() { }
+                for &(_, position) in self.breakpoints.iter() {
+                    interpreter.add_breakpoint(CellBreakpoint(position));
+                }
+            }
+            None => { }
+        }
+        body
+    }
+
+    fn first_syllable(&self, row: int) -> Option<(int, int)> {
+        let interpreter = match self.interpreter {
+            Some(ref interpreter) => interpreter,
+            None => { return None; }
+        };
+        let source = interpreter.source();
+        if row < 0 || row as uint >= source.height() {
+            return None;
+        }
+        for col in range(0, source.row_len(row as uint)) {
+            if source.get((row, col as int)).is_hangeul() {
+                return Some((row, col as int));
+            }
+        }
+        None
+    }
+
+    fn stack_trace(&self) -> String {
+        let interpreter = match self.interpreter {
+            Some(ref interpreter) => interpreter,
+            None => { return String::from_str("{\"stackFrames\":[],\"totalFrames\":0}"); }
+        };
+        let (row, col) = interpreter.counter();
+        let instruction = interpreter.instruction();
+        let name = match instruction.char() {
+            Some(c) => format!("{} {}", c, instruction.mnemonic()),
+            None => String::from_str("wall"),
+        };
+        format!("{{\"stackFrames\":[{{\"id\":{},\"name\":{},\"line\":{},\"column\":{},\"source\":{{\"path\":{}}}}}],\"totalFrames\":1}}",
+                FRAME_ID, quote(name.as_slice()), row + 1, col + 1, quote(self.path.as_slice()))
+    }
+
+    fn variables(&self, reference: uint) -> String {
+        let interpreter = match self.interpreter {
+            Some(ref interpreter) => interpreter,
+            None => { return String::from_str("{\"variables\":[]}"); }
+        };
+        let mut variables = Vec::new();
+        if reference == STORAGES_REFERENCE {
+            for index in range(0u, storage_names.len()) {
+                let kind = if index == 21 || index == 27 { "queue" } else { "stack" };
+                let name = format!("{} {}", index, storage_names[index]);
+                let value = format!("{}", interpreter.storage_at(index).values());
+                variables.push((String::from_str(name.as_slice().trim()), value, kind));
+            }
+        } else if reference == STATE_REFERENCE {
+            let (row, col) = interpreter.counter();
+            let (row_move, col_move) = interpreter.last_move();
+            variables.push((String::from_str("counter"), format!("({}, {})", row, col), "position"));
+            variables.push((String::from_str("direction"), String::from_str(interpreter.direction().name()), "direction"));
+            variables.push((String::from_str("last move"), format!("({}, {})", row_move, col_move), "position"));
+            variables.push((String::from_str("storage"), format!("{}", interpreter.storage_index()), "index"));
+        }
+        let entries: Vec<String> = variables.iter().map(|&(ref name, ref value, kind)| {
+            format!("{{\"name\":{},\"value\":{},\"type\":\"{}\",\"variablesReference\":0}}",
+                    quote(name.as_slice()), quote(value.as_slice()), kind)
+        }).collect();
+        format!("{{\"variables\":[{}]}}", entries.as_slice().connect(","))
+    }
+
+    /// Runs a batch of steps and reports how the program stopped, if it did.
+    fn advance(&mut self) -> IoResult<()> {
+        let result = match self.interpreter {
+            Some(ref mut interpreter) => guarded(interpreter, run_batch),
+            None => Ok(None),
+        };
+        try!(self.forward_output());
+        let result = match result {
+            Ok(result) => result,
+            Err(message) => { return self.failed(message.as_slice()); }
+        };
+        match result {
+            Some(Halted) => { try!(self.terminated(0)); }
+            Some(Stopped(BreakpointHit(idx))) => {
+                self.running = false;
+                let (id, _) = *self.breakpoints.get(idx);
+                let body = format!("{{\"reason\":\"breakpoint\",\"threadId\":{},\"hitBreakpointIds\":[{}]}}", THREAD_ID, id);
+                try!(self.event("stopped", body.as_slice()));
+            }
+            Some(Stopped(WatchpointHit(_))) => {
+                self.running = false;
+                try!(self.stopped("data breakpoint"));
+            }
+            Some(Stopped(InfiniteLoop(found))) => {
+                self.running = false;
+                let description = format!("infinite loop {}", found.describe());
+                let body = format!("{{\"reason\":\"exception\",\"threadId\":{},\"description\":{}}}",
+                                   THREAD_ID, quote(description.as_slice()));
+                try!(self.event("stopped", body.as_slice()));
+            }
+            None => { }
+        }
+        Ok(())
+    }
+
+    /// Executes one instruction and reports how the program stopped.
+    fn step(&mut self) -> IoResult<()> {
+        if self.halted {
+            return Ok(());
+        }
+        let halted = match self.interpreter {
+            Some(ref mut interpreter) => guarded(interpreter, step_syllable),
+            None => { return Ok(()); }
+        };
+        try!(self.forward_output());
+        match halted {
+            Ok(true) => self.terminated(0),
+            Ok(false) => self.stopped("step"),
+            Err(message) => self.failed(message.as_slice()),
+        }
+    }
+
+    fn forward_output(&mut self) -> IoResult<()> {
+        match self.interpreter {
+            Some(ref mut interpreter) => { interpreter.flush(); }
+            None => { }
+        }
+        let mut printed = Vec::new();
+        match self.output {
+            Some(ref output) => {
+                loop {
+                    match output.try_recv() {
+                        Ok(chunk) => { printed.push_all(chunk.as_slice()); }
+                        Err(_) => { break; }
+                    }
+                }
+            }
+            None => { }
+        }
+        if printed.is_empty() {
+            return Ok(());
+        }
+        let text = String::from_utf8(printed).ok().unwrap_or(String::new());
+        let body = format!("{{\"category\":\"stdout\",\"output\":{}}}", quote(text.as_slice()));
+        self.event("output", body.as_slice())
+    }
+
+    fn terminated(&mut self, exit_code: int) -> IoResult<()> {
+        self.running = false;
+        self.halted = true;
+        try!(self.event("exited", format!("{{\"exitCode\":{}}}", exit_code).as_slice()));
+        self.event("terminated", "{}")
+    }
+
+    /// Reports the failure of the interpreter and ends the session.
+    fn failed(&mut self, message: &str) -> IoResult<()> {
+        let body = format!("{{\"category\":\"stderr\",\"output\":{}}}", quote(format!("{}\n", message).as_slice()));
+        try!(self.event("output", body.as_slice()));
+        self.terminated(1)
+    }
+
+    fn stopped(&mut self, reason: &str) -> IoResult<()> {
+        let body = format!("{{\"reason\":\"{}\",\"threadId\":{}}}", reason, THREAD_ID);
+        self.event("stopped", body.as_slice())
+    }
+
+    fn respond(&mut self, request_seq: int, command: &str, body: &str) -> IoResult<()> {
+        self.seq += 1;
+        let message = format!("{{\"seq\":{},\"type\":\"response\",\"request_seq\":{},\"success\":true,\"command\":\"{}\",\"body\":{}}}",
+                              self.seq, request_seq, command, body);
+        write_message(&mut *self.out, message.as_slice())
+    }
+
+    fn fail(&mut self, request_seq: int, command: &str, error: &str) -> IoResult<()> {
+        self.seq += 1;
+        let message = format!("{{\"seq\":{},\"type\":\"response\",\"request_seq\":{},\"success\":false,\"command\":{},\"message\":{}}}",
+                              self.seq, request_seq, quote(command), quote(error));
+        write_message(&mut *self.out, message.as_slice())
+    }
+
+    fn event(&mut self, event: &str, body: &str) -> IoResult<()> {
+        self.seq += 1;
+        let message = format!("{{\"seq\":{},\"type\":\"event\",\"event\":\"{}\",\"body\":{}}}", self.seq, event, body);
+        write_message(&mut *self.out, message.as_slice())
+    }
+}
+
+/// Runs `f` on the interpreter in a task of its own, returning the message
+/// of the failure if the interpreter fails.
+fn guarded<T: Send>(interpreter: &mut Interpreter, f: fn(&mut Interpreter) -> T) -> Result<T, String> {
+    // The task is waited for, so the interpreter is never used by both.
+    let address = interpreter as *mut Interpreter as uint;
+    let result = TaskBuilder::new().stderr(box NullWriter).try(proc() {
+        f(unsafe { &mut *(address as *mut Interpreter) })
+    });
+    result.map_err(|cause| match cause.as_ref::<&'static str>() {
+        Some(message) => String::from_str(*message),
+        None => match cause.as_ref::<String>() {
+            Some(message) => message.clone(),
+            None => String::from_str("the program failed"),
+        },
+    })
+}
+
+fn run_batch(interpreter: &mut Interpreter) -> Option<RunResult> {
+    interpreter.run_steps(BATCH)
+}
+
+/// Executes one instruction, going through the walls without stopping.
+fn step_syllable(interpreter: &mut Interpreter) -> bool {
+    loop {
+        let halted = interpreter.step();
+        if halted || !interpreter.instruction().is_virtual() {
+            return halted;
+        }
+    }
+}
+
+fn field<'a>(json: &'a Json, key: &str) -> Option<&'a Json> {
+    json.find(&String::from_str(key))
+}
+
+fn string<'a>(json: &'a Json, key: &str) -> Option<&'a str> {
+    field(json, key).and_then(|value| value.as_string())
+}
+
+fn number(json: &Json, key: &str) -> Option<int> {
+    field(json, key).and_then(|value| value.as_number()).map(|n| n as int)
+}
@@ -0,0 +1,303 @@
+//! Line-oriented debugger driving an `Interpreter`.
+//!
+//! Commands:
+//!
+//! ```text
+//! step [N]            s   execute N instructions (1 by default)
+//! continue            c   run until a breakpoint, an infinite loop or the end
+//! back [N]            bs  undo N instructions (1 by default)
+//! reverse-continue    rc  step back until a breakpoint
+//! break ROW COL       b   stop when the counter reaches a cell
+//! break SYLLABLE      b   stop when the counter reaches a syllable
+//! delete [N]          d   remove breakpoint N, or all of them
+//! breakpoints         i   list breakpoints
+//! print               p   show the current storage
+//! storages                show all 28 storages
+//! where               w   show counter, direction, last move and storage
+//! grid [RADIUS]       l   show the rows around the counter
+//! save FILE               write a snapshot of the state
+//! load FILE               resume from a snapshot
+//! help                h
+//! quit                q
+//! ```
+//!
+//! An empty line repeats the previous command.
+
+use std::io::{File, IoResult};
+
+use super::{Interpreter, Storage, storage_names};
+use super::{Breakpoint, CellBreakpoint, DirectedCellBreakpoint, SyllableBreakpoint, OperationBreakpoint};
+use super::HaltKind;
+use super::{Halted, Stopped, BreakpointHit, WatchpointHit, InfiniteLoop};
+use history::{DEFAULT_INTERVAL, DEFAULT_LIMIT};
+use snapshot::Snapshot;
+
+static HELP: &'static str = "step [N]         execute N instructions
+continue         run until a breakpoint, an infinite loop or the end
+back [N]         undo N instructions
+reverse-continue step back until a breakpoint
+break ROW COL    stop when the counter reaches a cell
+break SYLLABLE   stop when the counter reaches a syllable
+delete [N]       remove breakpoint N, or all of them
+breakpoints      list breakpoints
+print            show the current storage
+storages         show all 28 storages
+where            show counter, direction, last move and storage
+grid [RADIUS]    show the rows around the counter
+save FILE        write a snapshot of the state
+load FILE        resume from a snapshot
+quit
+";
+
+pub struct Debugger {
+    interpreter: Interpreter,
+    halted: bool,
+    last_command: String,
+}
+
+impl Debugger {
+    /// Enables the history of `interpreter` so that it can step back.
+    pub fn new(mut interpreter: Interpreter) -> Debugger {
+        interpreter.enable_history(DEFAULT_INTERVAL, DEFAULT_LIMIT);
+        interpreter.enable_loop_detection();
+        Debugger {
+            interpreter: interpreter,
+            halted: false,
+            last_command: String::new(),
+        }
+    }
+
+    pub fn interpreter<'a>(&'a mut self) -> &'a mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Reads commands from `input` until `quit` or the end of input.
+    pub fn run(&mut self, input: &mut Buffer, out: &mut Writer) -> IoResult<()> {
+        try!(self.show_position(out));
+        loop {
+            try!(out.write_str("(aheui) "));
+            try!(out.flush());
+            let line = match input.read_line() {
+                Ok(line) => line,
+                Err(_) => { return Ok(()); }
+            };
+            if !try!(self.command(line.as_slice(), out)) {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Executes one command line. Returns false when the debugger should quit.
+    pub fn command(&mut self, line: &str, out: &mut Writer) -> IoResult<bool> {
+        let line = if line.trim().is_empty() {
+            self.last_command.clone()
+        } else {
+            String::from_str(line.trim())
+        };
+        self.last_command = line.clone();
+
+        let words: Vec<&str> = line.as_slice().words().collect();
+        if words.is_empty() {
+            return Ok(true);
+        }
+        let args = words.slice_from(1);
+        match *words.get(0) {
+            "step" | "s" => {
+                let count = match args.get(0) {
+                    Some(arg) => match from_str::<uint>(*arg) {
+                        Some(count) => count,
+                        None => { return self.usage(out, "step [N]"); }
+                    },
+                    None => 1,
+                };
+                for _ in range(0, count) {
+                    if self.halted {
+                        break;
+                    }
+                    self.halted = self.interpreter.step();
+                }
+                try!(self.show_position(out));
+            }
+            "continue" | "c" => {
+                if !self.halted {
+                    match self.interpreter.run() {
+                        Halted => { self.halted = true; }
+                        Stopped(BreakpointHit(idx)) => { try!(writeln!(out, "breakpoint {}", idx)); }
+                        Stopped(WatchpointHit(idx)) => { try!(writeln!(out, "watchpoint {}", idx)); }
+                        Stopped(InfiniteLoop(found)) => { try!(writeln!(out, "infinite loop {}", found.describe())); }
+                    }
+                }
+                try!(self.show_position(out));
+            }
+            "back" | "bs" => {
+                let count = match args.get(0) {
+                    Some(arg) => match from_str::<uint>(*arg) {
+                        Some(count) => count,
+                        None => { return self.usage(out, "back [N]"); }
+                    },
+                    None => 1,
+                };
+                for _ in range(0, count) {
+                    if !self.interpreter.step_back() {
+                        try!(writeln!(out, "no earlier step"));
+                        break;
+                    }
+                    self.halted = false;
+                }
+                try!(self.show_position(out));
+            }
+            "reverse-continue" | "rc" => {
+                match self.interpreter.run_back() {
+                    Some(idx) => { try!(writeln!(out, "breakpoint {}", idx)); }
+                    None => { try!(writeln!(out, "no earlier step")); }
+                }
+                // Any step back leaves the halting cell.
+                self.halted = self.halted && self.interpreter.instruction().operation().kind() == HaltKind;
+                try!(self.show_position(out));
+            }
+            "break" | "b" => {
+                let breakpoint = match args {
+                    [row, col] => match (from_str::<int>(row), from_str::<int>(col)) {
+                        (Some(row), Some(col)) => CellBreakpoint((row, col)),
+                        _ => { return self.usage(out, "break ROW COL | break SYLLABLE"); }
+                    },
+                    [syllable] if syllable.char_len() == 1 => SyllableBreakpoint(syllable.char_at(0)),
+                    _ => { return self.usage(out, "break ROW COL | break SYLLABLE"); }
+                };
+                let idx = self.interpreter.add_breakpoint(breakpoint);
+                try!(writeln!(out, "breakpoint {}: {}", idx, describe(&breakpoint)));
+            }
+            "delete" | "d" => {
+                match args.get(0) {
+                    Some(arg) => match from_str::<uint>(*arg).and_then(|idx| self.interpreter.remove_breakpoint(idx)) {
+                        Some(_) => { }
+                        None => { try!(writeln!(out, "no breakpoint {}", arg)); }
+                    },
+                    None => {
+                        while self.interpreter.remove_breakpoint(0).is_some() { }
+                    }
+                }
+            }
+            "breakpoints" | "i" => {
+                for (idx, breakpoint) in self.interpreter.breakpoints().iter().enumerate() {
+                    try!(writeln!(out, "{}: {}", idx, describe(breakpoint)));
+                }
+            }
+            "print" | "p" => {
+                let index = self.interpreter.storage_index();
+                try!(self.show_storage(out, index));
+            }
+            "storages" => {
+                for index in range(0u, 28) {
+                    try!(self.show_storage(out, index));
+                }
+            }
+            "where" | "w" => {
+                try!(self.show_position(out));
+                try!(writeln!(out, "direction {}, last move {}", self.interpreter.direction(), self.interpreter.last_move()));
+                let index = self.interpreter.storage_index();
+                try!(self.show_storage(out, index));
+            }
+            "grid" | "l" => {
+                let radius = match args.get(0) {
+                    Some(arg) => from_str::<uint>(*arg).unwrap_or(3),
+                    None => 3,
+                };
+                try!(self.show_grid(out, radius));
+            }
+            "save" => {
+                let path = match args {
+                    [path] => Path::new(path),
+                    _ => { return self.usage(out, "save FILE"); }
+                };
+                let result = File::create(&path).and_then(|mut file| self.interpreter.snapshot().write(&mut file));
+                match result {
+                    Ok(()) => { }
+                    Err(e) => { try!(writeln!(out, "cannot save: {}", e)); }
+                }
+            }
+            "load" => {
+                let path = match args {
+                    [path] => Path::new(path),
+                    _ => { return self.usage(out, "load FILE"); }
+                };
+                let result = match File::open(&path).and_then(|mut file| file.read_to_str()) {
+                    Ok(text) => Snapshot::from_str(text.as_slice()).and_then(|snapshot| self.interpreter.restore(&snapshot)),
+                    Err(e) => Err(format!("{}", e)),
+                };
+                match result {
+                    Ok(()) => {
+                        self.halted = false;
+                        try!(self.show_position(out));
+                    }
+                    Err(e) => { try!(writeln!(out, "cannot load: {}", e)); }
+                }
+            }
+            "help" | "h" => {
+                try!(out.write_str(HELP));
+            }
+            "quit" | "q" => {
+                return Ok(false);
+            }
+            command => {
+                try!(writeln!(out, "unknown command: {}", command));
+            }
+        }
+        self.interpreter.flush();
+        Ok(true)
+    }
+
+    fn usage(&self, out: &mut Writer, usage: &str) -> IoResult<bool> {
+        try!(writeln!(out, "usage: {}", usage));
+        Ok(true)
+    }
+
+    fn show_position(&self, out: &mut Writer) -> IoResult<()> {
+        let (row, col) = self.interpreter.counter();
+        let instruction = self.interpreter.instruction();
+        if self.halted {
+            try!(out.write_str("halted at "));
+        }
+        match instruction.char() {
+            Some(c) => writeln!(out, "({}, {}) {} {}", row, col, c, instruction.operation().name()),
+            None => writeln!(out, "({}, {}) wall", row, col),
+        }
+    }
+
+    fn show_storage(&self, out: &mut Writer, index: uint) -> IoResult<()> {
+        let storage = self.interpreter.storage_at(index);
+        let kind = if index == 21 || index == 27 { "queue" } else { "stack" };
+        let marker = if index == self.interpreter.storage_index() { "*" } else { " " };
+        writeln!(out, "{}{:2} {} {} {}", marker, index, storage_names[index], kind, storage.values())
+    }
+
+    fn show_grid(&self, out: &mut Writer, radius: uint) -> IoResult<()> {
+        let source = self.interpreter.source();
+        let (row, col) = self.interpreter.counter();
+        let first = if row > radius as int { row as uint - radius } else { 0 };
+        let last = ::std::cmp::min(source.height(), (::std::cmp::max(row, 0) as uint) + radius + 1);
+        for r in range(first, last) {
+            try!(write!(out, "{:4} ", r));
+            for c in range(0, source.row_len(r)) {
+                let here = (r as int, c as int) == (row, col);
+                let ch = source.get((r as int, c as int)).char().unwrap_or(' ');
+                if here {
+                    try!(write!(out, "[{}]", ch));
+                } else {
+                    try!(write!(out, " {} ", ch));
+                }
+            }
+            try!(writeln!(out, ""));
+        }
+        Ok(())
+    }
+}
+
+fn describe(breakpoint: &Breakpoint) -> String {
+    match *breakpoint {
+        CellBreakpoint((row, col)) => format!("({}, {})", row, col),
+        DirectedCellBreakpoint((row, col), direction) => format!("({}, {}) moving {}", row, col, direction),
+        SyllableBreakpoint(c) => format!("{}", c),
+        OperationBreakpoint(kind) => String::from_str(kind.name()),
+    }
+}
@@ -0,0 +1,278 @@
+//! Bounds on the number of values in each storage, found without running the
+//! program, to tell which operations underflow.
+//!
+//! An abstract state is a state of the `Graph` with the selected storage and
+//! an interval of lengths for each of the 28 storages. Input and arithmetic
+//! values are unknown, so both outcomes of a branch are followed. States
+//! reaching a cell again are joined; when the upper bound of a storage keeps
+//! growing it is widened to unbounded, so that loops pushing values end.
+
+use std::cmp;
+use std::collections::HashMap;
+
+use super::{Source, InstructionOperation};
+use super::{NoOperation, PushConstantOperation, PushDuplicationOperation, PushIntegerInputOperation};
+use super::{PushCharInputOperation, BinaryOperation, PopOperation, PrintIntegerOperation, PrintCharOperation};
+use super::{SwapOperation, ChangeStorageOperation, MoveToStorageOperation, CompareOperation, BranchOperation};
+use super::HaltOperation;
+use graph::{Graph, Goto};
+
+/// Joins of a state before its growing bounds are widened.
+static WIDENING_DELAY: uint = 3;
+
+/// Possible numbers of values in a storage. `hi` is None when unbounded.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Interval {
+    pub lo: uint,
+    pub hi: Option<uint>,
+}
+
+impl Interval {
+    fn exactly(n: uint) -> Interval {
+        Interval { lo: n, hi: Some(n) }
+    }
+
+    fn join(&self, other: &Interval) -> Interval {
+        Interval {
+            lo: cmp::min(self.lo, other.lo),
+            hi: match (self.hi, other.hi) {
+                (Some(a), Some(b)) => Some(cmp::max(a, b)),
+                _ => None,
+            },
+        }
+    }
+
+    fn at_least(&self, n: uint) -> bool {
+        match self.hi {
+            Some(hi) => hi >= n,
+            None => true,
+        }
+    }
+
+    fn add(&self, delta: int) -> Interval {
+        let shift = |n: uint| if delta < 0 { n - (-delta) as uint } else { n + delta as uint };
+        Interval { lo: shift(self.lo), hi: self.hi.map(shift) }
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Underflow {
+    Never,
+    /// Underflows on some paths but not on others.
+    Sometimes,
+    Always,
+}
+
+/// Values an operation picks from the selected storage, which it needs to
+/// be there not to reflect.
+pub fn required(operation: InstructionOperation) -> uint {
+    match operation {
+        BinaryOperation(_) | SwapOperation | CompareOperation => 2,
+        PopOperation | PrintIntegerOperation | PrintCharOperation | PushDuplicationOperation
+            | MoveToStorageOperation(_) | BranchOperation => 1,
+        _ => 0,
+    }
+}
+
+#[deriving(Clone, PartialEq)]
+struct Lengths {
+    storage_index: uint,
+    storages: Vec<Interval>,
+}
+
+impl Lengths {
+    fn join(&self, other: &Lengths) -> Lengths {
+        Lengths {
+            storage_index: self.storage_index,
+            storages: self.storages.iter().zip(other.storages.iter()).map(|(a, b)| a.join(b)).collect(),
+        }
+    }
+
+    /// Drops the upper bounds which grew since `old`.
+    fn widen(&self, old: &Lengths) -> Lengths {
+        Lengths {
+            storage_index: self.storage_index,
+            storages: self.storages.iter().zip(old.storages.iter()).map(|(new, old)| {
+                match (new.hi, old.hi) {
+                    (Some(a), Some(b)) if a > b => Interval { lo: new.lo, hi: None },
+                    _ => *new,
+                }
+            }).collect(),
+        }
+    }
+
+    fn selected(&self) -> Interval {
+        *self.storages.get(self.storage_index)
+    }
+
+    fn set_selected(&mut self, interval: Interval) {
+        *self.storages.get_mut(self.storage_index) = interval;
+    }
+
+    /// State after `operation` found enough values.
+    fn apply(&self, operation: InstructionOperation) -> Lengths {
+        let mut next = self.clone();
+        let need = required(operation);
+        let current = self.selected();
+        next.set_selected(Interval { lo: cmp::max(current.lo, need), hi: current.hi });
+        match operation {
+            PushConstantOperation(_) | PushDuplicationOperation | PushIntegerInputOperation | PushCharInputOperation => {
+                let selected = next.selected().add(1);
+                next.set_selected(selected);
+            }
+            BinaryOperation(_) | PopOperation | PrintIntegerOperation | PrintCharOperation
+                | CompareOperation | BranchOperation => {
+                let selected = next.selected().add(-1);
+                next.set_selected(selected);
+            }
+            MoveToStorageOperation(index) => {
+                let selected = next.selected().add(-1);
+                next.set_selected(selected);
+                next.storage_index = index;
+                let target = next.selected().add(1);
+                next.set_selected(target);
+            }
+            ChangeStorageOperation(index) => {
+                next.storage_index = index;
+            }
+            SwapOperation | NoOperation | HaltOperation => { }
+        }
+        next
+    }
+
+    /// State after `operation` lacked values, if it can.
+    fn lacking(&self, operation: InstructionOperation) -> Option<Lengths> {
+        let need = required(operation);
+        let current = self.selected();
+        if need == 0 || current.lo >= need {
+            return None;
+        }
+        let mut next = self.clone();
+        next.set_selected(Interval { lo: current.lo, hi: Some(match current.hi {
+            Some(hi) => cmp::min(hi, need - 1),
+            None => need - 1,
+        }) });
+        Some(next)
+    }
+}
+
+pub struct DepthAnalysis {
+    /// How the reachable cells whose operation needs values underflow.
+    pub cells: HashMap<(int, int), Underflow>,
+    /// Lengths of the selected storage when a cell is reached, joined over
+    /// every path.
+    pub entry: HashMap<(int, int), Interval>,
+}
+
+impl DepthAnalysis {
+    pub fn from_source(source: &Source) -> DepthAnalysis {
+        DepthAnalysis::from_graph(&Graph::from_source(source))
+    }
+
+    pub fn from_graph(graph: &Graph) -> DepthAnalysis {
+        let mut states: HashMap<(uint, uint), Lengths> = HashMap::new();
+        let mut joins: HashMap<(uint, uint), uint> = HashMap::new();
+        let mut worklist: Vec<(uint, uint)> = Vec::new();
+        let start = Lengths { storage_index: 0, storages: Vec::from_fn(28, |_| Interval::exactly(0)) };
+        if graph.len() > 0 {
+            states.insert((0, 0), start);
+            worklist.push((0, 0));
+        }
+
+        while !worklist.is_empty() {
+            let key = worklist.pop().unwrap();
+            let (idx, _) = key;
+            let lengths = states.get(&key).clone();
+            let node = graph.nodes.get(idx);
+            let operation = node.instruction.operation();
+            let mut successors = Vec::new();
+            match operation {
+                HaltOperation => { }
+                BranchOperation => {
+                    // A zero takes the reflected edge as an underflow does.
+                    if lengths.selected().at_least(1) {
+                        let popped = lengths.apply(operation);
+                        successors.push((node.next, popped.clone()));
+                        match node.reflected {
+                            Some(edge) => { successors.push((edge, popped)); }
+                            None => { }
+                        }
+                    }
+                    match (node.reflected, lengths.lacking(operation)) {
+                        (Some(edge), Some(lacking)) => { successors.push((edge, lacking)); }
+                        _ => { }
+                    }
+                }
+                _ => {
+                    if lengths.selected().at_least(required(operation)) {
+                        successors.push((node.next, lengths.apply(operation)));
+                    }
+                    match (node.reflected, lengths.lacking(operation)) {
+                        (Some(edge), Some(lacking)) => { successors.push((edge, lacking)); }
+                        _ => { }
+                    }
+                }
+            }
+
+            for &(edge, ref next) in successors.iter() {
+                let target = match edge {
+                    Goto(target) => (target, next.storage_index),
+                    _ => { continue; }
+                };
+                let merged = match states.find(&target) {
+                    Some(old) => {
+                        let joined = old.join(next);
+                        if joined == *old {
+                            continue;
+                        }
+                        let count = joins.find(&target).map(|n| *n).unwrap_or(0) + 1;
+                        joins.insert(target, count);
+                        if count > WIDENING_DELAY { joined.widen(old) } else { joined }
+                    }
+                    None => next.clone(),
+                };
+                states.insert(target, merged);
+                if !worklist.contains(&target) {
+                    worklist.push(target);
+                }
+            }
+        }
+
+        let mut analysis = DepthAnalysis { cells: HashMap::new(), entry: HashMap::new() };
+        for (&(idx, _), lengths) in states.iter() {
+            let node = graph.nodes.get(idx);
+            if !node.instruction.is_hangeul() {
+                continue;
+            }
+            let position = node.state.position();
+            let selected = lengths.selected();
+            let entry = match analysis.entry.find(&position) {
+                Some(interval) => interval.join(&selected),
+                None => selected,
+            };
+            analysis.entry.insert(position, entry);
+
+            let need = required(node.instruction.operation());
+            if need == 0 {
+                continue;
+            }
+            let underflow = if selected.lo >= need {
+                Never
+            } else if !selected.at_least(need) {
+                Always
+            } else {
+                Sometimes
+            };
+            let merged = match analysis.cells.find(&position) {
+                Some(previous) if *previous != underflow => Sometimes,
+                _ => underflow,
+            };
+            analysis.cells.insert(position, merged);
+        }
+        analysis
+    }
+
+    pub fn underflow(&self, position: (int, int)) -> Option<Underflow> {
+        self.cells.find(&position).map(|underflow| *underflow)
+    }
+}
@@ -0,0 +1,106 @@
+//! Linear listing of a program along its execution paths.
+//!
+//! The runs of syllables found by `ControlFlow` are listed under labels, one
+//! syllable per line with its position, mnemonic and the direction the
+//! counter leaves in. A run is cut where the counter wraps around an edge of
+//! the program, so that every wrap shows up as a jump like a branch does.
+//! Syllables which never run are not listed.
+
+use std::io::IoResult;
+
+use super::{Source, Down, Up, Right, Left};
+use graph::{Graph, Goto};
+use cfg::ControlFlow;
+
+/// Consecutive syllables of a run listed under one label.
+struct Segment {
+    block: uint,
+    nodes: Vec<uint>,
+}
+
+pub struct Listing {
+    flow: ControlFlow,
+    segments: Vec<Segment>,
+    /// Label of the first segment of each block.
+    labels: Vec<uint>,
+}
+
+impl Listing {
+    pub fn from_source(source: &Source) -> Listing {
+        let flow = ControlFlow::from_source(source);
+        let mut segments = Vec::new();
+        let mut labels = Vec::new();
+        for (i, block) in flow.blocks.iter().enumerate() {
+            labels.push(segments.len());
+            let mut nodes = vec!(*block.nodes.get(0));
+            for pair in block.nodes.as_slice().windows(2) {
+                if wraps(&flow.graph, pair[0], pair[1]) {
+                    segments.push(Segment { block: i, nodes: nodes });
+                    nodes = Vec::new();
+                }
+                nodes.push(pair[1]);
+            }
+            segments.push(Segment { block: i, nodes: nodes });
+        }
+        Listing { flow: flow, segments: segments, labels: labels }
+    }
+
+    /// Writes lines like `(0,0) 밤 push 4 ↓` under labels `L0:`, `L1:` and
+    /// so on, each run ending in the jumps it may take.
+    pub fn write(&self, out: &mut Writer) -> IoResult<()> {
+        let graph = &self.flow.graph;
+        for (label, segment) in self.segments.iter().enumerate() {
+            try!(writeln!(out, "L{}:", label));
+            for idx in segment.nodes.iter() {
+                let node = graph.nodes.get(*idx);
+                let (row, col) = node.state.position();
+                let leaving = match node.next {
+                    Goto(next) => format!(" {}", arrow(graph, next)),
+                    _ => String::new(),
+                };
+                try!(writeln!(out, "    ({},{}) {} {}{}", row, col, node.instruction.char().unwrap(),
+                              node.instruction.mnemonic(), leaving));
+            }
+            let last = *segment.nodes.last().unwrap();
+            let block = self.flow.blocks.get(segment.block);
+            if last != *block.nodes.last().unwrap() {
+                try!(writeln!(out, "    jump L{} (wrap)", label + 1));
+                continue;
+            }
+            for exit in block.exits.iter() {
+                let target = *self.flow.blocks.get(exit.target).nodes.get(0);
+                let outcome = match exit.outcome.name() {
+                    Some(outcome) => format!("{}: ", outcome),
+                    None => String::new(),
+                };
+                try!(writeln!(out, "    {}jump L{}{}", outcome, *self.labels.get(exit.target),
+                              if wraps(graph, last, target) { " (wrap)" } else { "" }));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether the counter crosses an edge of the program going from one
+/// syllable to the next, as told by the direction it arrives in.
+fn wraps(graph: &Graph, from: uint, to: uint) -> bool {
+    let (from_row, from_col) = graph.nodes.get(from).state.position();
+    let target = graph.nodes.get(to).state;
+    let (to_row, to_col) = target.position();
+    match target.direction {
+        Down => to_row <= from_row,
+        Up => to_row >= from_row,
+        Right => to_col <= from_col,
+        Left => to_col >= from_col,
+    }
+}
+
+/// Arrow of the direction the counter has when it reaches a node.
+fn arrow(graph: &Graph, idx: uint) -> &'static str {
+    match graph.nodes.get(idx).state.direction {
+        Down => "↓",
+        Up => "↑",
+        Right => "→",
+        Left => "←",
+    }
+}
@@ -0,0 +1,663 @@
+//! Static x86-64 Linux executables generated from a `Source`.
+//!
+//! The executable holds the program compiled state by state, with the
+//! operations `jit` also uses, and a small runtime written directly in
+//! machine code: storages, buffered output and UTF-8 aware input, all on raw
+//! syscalls. Nothing is linked.
+//!
+//! Every storage is a ring buffer of `CAPACITY` values; putting one more
+//! stops the program with an error message, as dividing by zero, printing a
+//! value which is no character and leaving the map do. Unlike the
+//! interpreter, reading at the end of input pushes -1 instead of failing.
+
+use std::io;
+use std::io::{IoResult, File, fs};
+
+use super::{Source, Instruction};
+use super::{NoOperation, PushConstantOperation, PushDuplicationOperation, PushIntegerInputOperation,
+            PushCharInputOperation, BinaryOperation, PopOperation, PrintIntegerOperation,
+            PrintCharOperation, SwapOperation, ChangeStorageOperation, MoveToStorageOperation,
+            CompareOperation, BranchOperation, HaltOperation};
+use graph::{Graph, Goto, Halt, Escape};
+use x86::{Assembler, Label, RAX, RCX, RDX, RBX, RSI, RDI, R8, R9, R10, R15};
+use x86::{Below, BelowEqual, Above, Equal, NotEqual, Less, LessEqual, GreaterEqual, NotSign};
+
+/// Values each storage can hold.
+pub static CAPACITY: uint = 1 << 20;
+
+static BASE_ADDRESS: u64 = 0x400000;
+static PAGE_SIZE: u64 = 0x1000;
+static HEADERS_SIZE: uint = 64 + 2 * 56;
+
+// Layout of the writable segment, addressed from r15. It starts with a
+// descriptor per storage, holding the address of its buffer, the offset of
+// its first value, its length, the offset mask and whether it is a queue.
+pub static DESCRIPTOR_SIZE: i32 = 40;
+pub static BASE: i32 = 0;
+pub static HEAD: i32 = 8;
+pub static LEN: i32 = 16;
+pub static MASK: i32 = 24;
+pub static QUEUE: i32 = 32;
+static OUT_LEN: i32 = 28 * DESCRIPTOR_SIZE;
+static IN_POS: i32 = OUT_LEN + 8;
+static IN_LEN: i32 = IN_POS + 8;
+static NUMBER: i32 = IN_LEN + 8;
+static NUMBER_END: i32 = NUMBER + 32;
+static OUT: i32 = NUMBER_END;
+static IN: i32 = OUT + 4096;
+static BUFFERS: i32 = IN + 4096;
+static IO_SIZE: i32 = 4096;
+
+/// Routines compiled operations call. Storage routines take the current
+/// descriptor in rbx and values in rax; they only clobber rax and rcx. I/O
+/// routines may clobber every register but rbx and r15. `overflow` is jumped
+/// to from `reserve` when a storage is full, and returns to it if it could
+/// make room; `divide_by_zero` is jumped to from the compiled code.
+pub struct Runtime {
+    pub put: Label,
+    pub rput: Label,
+    pub reserve: Label,
+    pub overflow: Label,
+    pub pick: Label,
+    pub peek: Label,
+    pub print_int: Label,
+    pub print_char: Label,
+    pub read_int: Label,
+    pub read_char: Label,
+    pub divide_by_zero: Label,
+}
+
+impl Runtime {
+    pub fn new(asm: &mut Assembler) -> Runtime {
+        Runtime {
+            put: asm.new_label(), rput: asm.new_label(), reserve: asm.new_label(), overflow: asm.new_label(),
+            pick: asm.new_label(), peek: asm.new_label(), print_int: asm.new_label(),
+            print_char: asm.new_label(), read_int: asm.new_label(), read_char: asm.new_label(),
+            divide_by_zero: asm.new_label(),
+        }
+    }
+}
+
+/// Routines of the executable on top of `Runtime`.
+struct System {
+    write_byte: Label,
+    flush: Label,
+    read_byte: Label,
+    exit: Label,
+    escape: Label,
+    invalid_char: Label,
+}
+
+/// Returns the bytes of an executable running `source`.
+pub fn build(source: &Source) -> Vec<u8> {
+    let graph = Graph::from_source(source);
+    let mut asm = Assembler::new();
+    let rt = Runtime::new(&mut asm);
+    let sys = System {
+        write_byte: asm.new_label(), flush: asm.new_label(), read_byte: asm.new_label(),
+        exit: asm.new_label(), escape: asm.new_label(), invalid_char: asm.new_label(),
+    };
+    let labels = Vec::from_fn(graph.len(), |_| asm.new_label());
+
+    // Every storage starts empty at the beginning of its buffer.
+    asm.mov_imm(R15, 0);
+    let bss_imm = asm.len() - 8;
+    for i in range(0i32, 28) {
+        let descriptor = i * DESCRIPTOR_SIZE;
+        asm.lea(RAX, R15, BUFFERS + i * (CAPACITY as i32) * 8);
+        asm.store(R15, descriptor + BASE, RAX);
+        asm.mov_imm(RAX, (CAPACITY - 1) as u64);
+        asm.store(R15, descriptor + MASK, RAX);
+        asm.mov_imm(RAX, if i == 21 || i == 27 { 1 } else { 0 });
+        asm.store(R15, descriptor + QUEUE, RAX);
+    }
+    asm.lea(RBX, R15, 0);
+
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        asm.bind(*labels.get(idx));
+        let reflect = match node.reflected {
+            Some(Goto(target)) => *labels.get(target),
+            _ => sys.escape,
+        };
+        compile_operation(&mut asm, &rt, &node.instruction, reflect);
+        match node.next {
+            Goto(next) if next == idx + 1 => { }
+            Goto(next) => { asm.jmp(*labels.get(next)); }
+            Halt => { asm.jmp(sys.exit); }
+            Escape => { asm.jmp(sys.escape); }
+        }
+    }
+    compile_storages(&mut asm, &rt);
+    compile_runtime(&mut asm, &rt, &sys);
+
+    let mut code = asm.finish();
+    let file_size = HEADERS_SIZE + code.len();
+    let bss_address = (BASE_ADDRESS + file_size as u64 + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let bss_size = BUFFERS as u64 + 28 * CAPACITY as u64 * 8;
+    for i in range(0u, 8) {
+        *code.get_mut(bss_imm + i) = (bss_address >> (8 * i)) as u8;
+    }
+
+    let mut elf = Vec::new();
+    // ELF header
+    elf.push_all(&[0x7f, 'E' as u8, 'L' as u8, 'F' as u8, 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    push_u16(&mut elf, 2); // ET_EXEC
+    push_u16(&mut elf, 0x3e); // EM_X86_64
+    push_u32(&mut elf, 1);
+    push_u64(&mut elf, BASE_ADDRESS + HEADERS_SIZE as u64);
+    push_u64(&mut elf, 64); // program headers
+    push_u64(&mut elf, 0); // no section headers
+    push_u32(&mut elf, 0);
+    push_u16(&mut elf, 64);
+    push_u16(&mut elf, 56);
+    push_u16(&mut elf, 2);
+    push_u16(&mut elf, 64);
+    push_u16(&mut elf, 0);
+    push_u16(&mut elf, 0);
+    // PT_LOAD, r-x: headers and code
+    push_segment(&mut elf, 5, 0, BASE_ADDRESS, file_size as u64, file_size as u64);
+    // PT_LOAD, rw-: storages and buffers
+    push_segment(&mut elf, 6, 0, bss_address, 0, bss_size);
+    elf.push_all(code.as_slice());
+    elf
+}
+
+/// Writes an executable running `source` to `path`.
+pub fn write_executable(source: &Source, path: &Path) -> IoResult<()> {
+    let mut file = try!(File::create(path));
+    try!(file.write(build(source).as_slice()));
+    fs::chmod(path, io::UserRWX | io::GroupRead | io::GroupExecute | io::OtherRead | io::OtherExecute)
+}
+
+fn push_u16(v: &mut Vec<u8>, n: u16) {
+    v.push(n as u8);
+    v.push((n >> 8) as u8);
+}
+
+fn push_u32(v: &mut Vec<u8>, n: u32) {
+    push_u16(v, n as u16);
+    push_u16(v, (n >> 16) as u16);
+}
+
+fn push_u64(v: &mut Vec<u8>, n: u64) {
+    push_u32(v, n as u32);
+    push_u32(v, (n >> 32) as u32);
+}
+
+fn push_segment(v: &mut Vec<u8>, flags: u32, offset: u64, address: u64, file_size: u64, memory_size: u64) {
+    push_u32(v, 1);
+    push_u32(v, flags);
+    push_u64(v, offset);
+    push_u64(v, address);
+    push_u64(v, address);
+    push_u64(v, file_size);
+    push_u64(v, memory_size);
+    push_u64(v, PAGE_SIZE);
+}
+
+/// Jumps to `reflect` unless the current storage holds `count` values.
+fn require(asm: &mut Assembler, count: i32, reflect: Label) {
+    asm.load(RAX, RBX, LEN);
+    asm.cmp_imm(RAX, count);
+    asm.jcc(Less, reflect);
+}
+
+/// Compiles an operation, jumping to `reflect` if it reverses the movement.
+/// rbx holds the descriptor of the selected storage and r15 the first one.
+pub fn compile_operation(asm: &mut Assembler, rt: &Runtime, instruction: &Instruction, reflect: Label) {
+    match instruction.operation {
+        NoOperation => { }
+        PushConstantOperation(v) => {
+            asm.mov_imm(RAX, v as u64);
+            asm.call(rt.put);
+        }
+        PushDuplicationOperation => {
+            require(asm, 1, reflect);
+            asm.call(rt.peek);
+            asm.call(rt.put);
+        }
+        PushIntegerInputOperation => {
+            asm.call(rt.read_int);
+            asm.call(rt.put);
+        }
+        PushCharInputOperation => {
+            asm.call(rt.read_char);
+            asm.call(rt.put);
+        }
+        BinaryOperation(_) => {
+            require(asm, 2, reflect);
+            asm.call(rt.pick);
+            asm.mov(R8, RAX);
+            asm.call(rt.pick);
+            match instruction.binary_operator().unwrap() {
+                "add" => { asm.add(RAX, R8); }
+                "mul" => { asm.imul(RAX, R8); }
+                "sub" => { asm.sub(RAX, R8); }
+                operator => {
+                    asm.test(R8, R8);
+                    asm.jcc(Equal, rt.divide_by_zero);
+                    asm.idiv(R8);
+                    if operator == "mod" {
+                        asm.mov(RAX, RDX);
+                    }
+                }
+            }
+            asm.call(rt.put);
+        }
+        PopOperation => {
+            require(asm, 1, reflect);
+            asm.call(rt.pick);
+        }
+        PrintIntegerOperation => {
+            require(asm, 1, reflect);
+            asm.call(rt.pick);
+            asm.call(rt.print_int);
+        }
+        PrintCharOperation => {
+            require(asm, 1, reflect);
+            asm.call(rt.pick);
+            asm.call(rt.print_char);
+        }
+        SwapOperation => {
+            require(asm, 2, reflect);
+            asm.call(rt.pick);
+            asm.mov(R8, RAX);
+            asm.call(rt.pick);
+            asm.mov(R9, RAX);
+            asm.mov(RAX, R8);
+            asm.call(rt.rput);
+            asm.mov(RAX, R9);
+            asm.call(rt.rput);
+        }
+        ChangeStorageOperation(index) => {
+            asm.lea(RBX, R15, index as i32 * DESCRIPTOR_SIZE);
+        }
+        MoveToStorageOperation(index) => {
+            require(asm, 1, reflect);
+            asm.call(rt.pick);
+            asm.lea(RBX, R15, index as i32 * DESCRIPTOR_SIZE);
+            asm.call(rt.put);
+        }
+        CompareOperation => {
+            require(asm, 2, reflect);
+            asm.call(rt.pick);
+            asm.mov(R8, RAX);
+            asm.call(rt.pick);
+            let done = asm.new_label();
+            asm.cmp(RAX, R8);
+            asm.mov_imm(RAX, 1);
+            asm.jcc(GreaterEqual, done);
+            asm.mov_imm(RAX, 0);
+            asm.bind(done);
+            asm.call(rt.put);
+        }
+        BranchOperation => {
+            require(asm, 1, reflect);
+            asm.call(rt.pick);
+            asm.test(RAX, RAX);
+            asm.jcc(Equal, reflect);
+        }
+        HaltOperation => { }
+    }
+}
+
+/// Writes the byte `(r10 >> shift) & 0x3f | 0x80`.
+fn continuation_byte(asm: &mut Assembler, sys: &System, shift: i8) {
+    asm.mov(RAX, R10);
+    if shift > 0 {
+        asm.shift(RAX, -shift);
+    }
+    asm.and_imm(RAX, 0x3f);
+    asm.add_imm(RAX, 0x80);
+    asm.call(sys.write_byte);
+}
+
+/// Writes the byte `(r10 >> shift) + marker`.
+fn leading_byte(asm: &mut Assembler, sys: &System, shift: i8, marker: i32) {
+    asm.mov(RAX, R10);
+    asm.shift(RAX, -shift);
+    asm.add_imm(RAX, marker);
+    asm.call(sys.write_byte);
+}
+
+/// `rcx = rax - '0'`, jumping to `label` if it is (or is not) a digit.
+fn digit(asm: &mut Assembler, label: Label, is_digit: bool) {
+    asm.mov(RCX, RAX);
+    asm.sub_imm(RCX, '0' as i32);
+    asm.cmp_imm(RCX, 9);
+    asm.jcc(if is_digit { BelowEqual } else { Above }, label);
+}
+
+fn syscall(asm: &mut Assembler, number: u64, fd: u64) {
+    asm.mov_imm(RAX, number);
+    asm.mov_imm(RDI, fd);
+}
+
+/// Compiles the storage routines of `rt`.
+pub fn compile_storages(asm: &mut Assembler, rt: &Runtime) {
+    // Offsets wrap around the buffer, so that a queue can keep moving
+    // forward. A stack keeps its first value at offset 0. reserve returns
+    // if there is room for one more value.
+    asm.bind(rt.reserve);
+    asm.push(RAX);
+    asm.load(RAX, RBX, MASK);
+    asm.load(RCX, RBX, LEN);
+    asm.cmp(RCX, RAX);
+    asm.pop(RAX);
+    asm.jcc(Above, rt.overflow);
+    asm.ret();
+
+    asm.bind(rt.put);
+    asm.call(rt.reserve);
+    asm.push(RDX);
+    asm.load(RCX, RBX, HEAD);
+    asm.load(RDX, RBX, LEN);
+    asm.add(RCX, RDX);
+    asm.add_imm(RDX, 1);
+    asm.store(RBX, LEN, RDX);
+    element(asm);
+    asm.store(RCX, 0, RAX);
+    asm.pop(RDX);
+    asm.ret();
+
+    asm.bind(rt.rput);
+    asm.load(RCX, RBX, QUEUE);
+    asm.test(RCX, RCX);
+    asm.jcc(Equal, rt.put);
+    asm.call(rt.reserve);
+    asm.push(RDX);
+    asm.load(RCX, RBX, HEAD);
+    asm.sub_imm(RCX, 1);
+    asm.load(RDX, RBX, MASK);
+    asm.and(RCX, RDX);
+    asm.store(RBX, HEAD, RCX);
+    asm.load(RDX, RBX, LEN);
+    asm.add_imm(RDX, 1);
+    asm.store(RBX, LEN, RDX);
+    element(asm);
+    asm.store(RCX, 0, RAX);
+    asm.pop(RDX);
+    asm.ret();
+
+    // pick and peek expect a value in the storage.
+    let stack = asm.new_label();
+    let take = asm.new_label();
+    asm.bind(rt.pick);
+    asm.push(RDX);
+    asm.load(RCX, RBX, HEAD);
+    asm.load(RAX, RBX, QUEUE);
+    asm.test(RAX, RAX);
+    asm.jcc(Equal, stack);
+    asm.mov(RAX, RCX);
+    asm.add_imm(RAX, 1);
+    asm.load(RDX, RBX, MASK);
+    asm.and(RAX, RDX);
+    asm.store(RBX, HEAD, RAX);
+    asm.jmp(take);
+    asm.bind(stack);
+    last_offset(asm);
+    asm.bind(take);
+    asm.load(RDX, RBX, LEN);
+    asm.sub_imm(RDX, 1);
+    asm.store(RBX, LEN, RDX);
+    element(asm);
+    asm.load(RAX, RCX, 0);
+    asm.pop(RDX);
+    asm.ret();
+
+    let front = asm.new_label();
+    asm.bind(rt.peek);
+    asm.push(RDX);
+    asm.load(RCX, RBX, HEAD);
+    asm.load(RAX, RBX, QUEUE);
+    asm.test(RAX, RAX);
+    asm.jcc(NotEqual, front);
+    last_offset(asm);
+    asm.bind(front);
+    element(asm);
+    asm.load(RAX, RCX, 0);
+    asm.pop(RDX);
+    asm.ret();
+}
+
+fn compile_runtime(asm: &mut Assembler, rt: &Runtime, sys: &System) {
+    let done = asm.new_label();
+    asm.bind(sys.write_byte);
+    asm.load(RCX, R15, OUT_LEN);
+    asm.lea(RDX, R15, OUT);
+    asm.add(RDX, RCX);
+    asm.store_byte(RDX, 0, RAX);
+    asm.add_imm(RCX, 1);
+    asm.store(R15, OUT_LEN, RCX);
+    asm.cmp_imm(RCX, IO_SIZE);
+    asm.jcc(Less, done);
+    asm.call(sys.flush);
+    asm.bind(done);
+    asm.ret();
+
+    asm.bind(sys.flush);
+    syscall(asm, 1, 1); // write(1, out, out_len)
+    asm.lea(RSI, R15, OUT);
+    asm.load(RDX, R15, OUT_LEN);
+    asm.syscall();
+    asm.mov_imm(RCX, 0);
+    asm.store(R15, OUT_LEN, RCX);
+    asm.ret();
+
+    let positive = asm.new_label();
+    let convert = asm.new_label();
+    let output = asm.new_label();
+    asm.bind(rt.print_int);
+    asm.mov(R10, RAX);
+    asm.test(R10, R10);
+    asm.jcc(NotSign, positive);
+    asm.mov_imm(RAX, '-' as u64);
+    asm.call(sys.write_byte);
+    asm.neg(R10);
+    asm.bind(positive);
+    asm.lea(RSI, R15, NUMBER_END);
+    asm.mov(RAX, R10);
+    asm.mov_imm(RDI, 10);
+    asm.bind(convert);
+    asm.idiv(RDI);
+    asm.add_imm(RDX, '0' as i32);
+    asm.sub_imm(RSI, 1);
+    asm.store_byte(RSI, 0, RDX);
+    asm.test(RAX, RAX);
+    asm.jcc(NotEqual, convert);
+    asm.mov(R10, RSI);
+    asm.bind(output);
+    asm.load_byte(RAX, R10, 0);
+    asm.call(sys.write_byte);
+    asm.add_imm(R10, 1);
+    asm.lea(RCX, R15, NUMBER_END);
+    asm.cmp(R10, RCX);
+    asm.jcc(Below, output);
+    asm.ret();
+
+    let two = asm.new_label();
+    let three = asm.new_label();
+    let tail2 = asm.new_label();
+    let tail1 = asm.new_label();
+    // Negative values, surrogates and values past U+10FFFF are no characters.
+    asm.bind(rt.print_char);
+    asm.cmp_imm(RAX, 0x10ffff);
+    asm.jcc(Above, sys.invalid_char);
+    asm.mov(R10, RAX);
+    asm.sub_imm(R10, 0xd800);
+    asm.cmp_imm(R10, 0x7ff);
+    asm.jcc(BelowEqual, sys.invalid_char);
+    asm.cmp_imm(RAX, 0x80);
+    asm.jcc(Below, sys.write_byte);
+    asm.mov(R10, RAX);
+    asm.cmp_imm(RAX, 0x800);
+    asm.jcc(Below, two);
+    asm.cmp_imm(RAX, 0x10000);
+    asm.jcc(Below, three);
+    leading_byte(asm, sys, 18, 0xf0);
+    continuation_byte(asm, sys, 12);
+    asm.jmp(tail2);
+    asm.bind(three);
+    leading_byte(asm, sys, 12, 0xe0);
+    asm.jmp(tail2);
+    asm.bind(two);
+    leading_byte(asm, sys, 6, 0xc0);
+    asm.jmp(tail1);
+    asm.bind(tail2);
+    continuation_byte(asm, sys, 6);
+    asm.bind(tail1);
+    continuation_byte(asm, sys, 0);
+    asm.ret();
+
+    // read_byte returns the next input byte in rax, or -1 at the end.
+    let have = asm.new_label();
+    let eof = asm.new_label();
+    asm.bind(sys.read_byte);
+    asm.load(RCX, R15, IN_POS);
+    asm.load(RDX, R15, IN_LEN);
+    asm.cmp(RCX, RDX);
+    asm.jcc(Below, have);
+    asm.call(sys.flush);
+    syscall(asm, 0, 0); // read(0, in, 4096)
+    asm.lea(RSI, R15, IN);
+    asm.mov_imm(RDX, IO_SIZE as u64);
+    asm.syscall();
+    asm.store(R15, IN_LEN, RAX);
+    asm.test(RAX, RAX);
+    asm.jcc(LessEqual, eof);
+    asm.mov_imm(RCX, 0);
+    asm.bind(have);
+    asm.lea(RDX, R15, IN);
+    asm.add(RDX, RCX);
+    asm.load_byte(RAX, RDX, 0);
+    asm.add_imm(RCX, 1);
+    asm.store(R15, IN_POS, RCX);
+    asm.ret();
+    asm.bind(eof);
+    asm.mov_imm(RCX, 0);
+    asm.store(R15, IN_LEN, RCX);
+    asm.store(R15, IN_POS, RCX);
+    asm.mov_imm(RAX, -1i64 as u64);
+    asm.ret();
+
+    // read_int skips to the first digit or minus sign and reads a decimal
+    // number, consuming the byte after it.
+    let skip = asm.new_label();
+    let negative = asm.new_label();
+    let number = asm.new_label();
+    let done = asm.new_label();
+    let eof = asm.new_label();
+    asm.bind(rt.read_int);
+    asm.bind(skip);
+    asm.call(sys.read_byte);
+    asm.cmp_imm(RAX, -1);
+    asm.jcc(Equal, eof);
+    asm.cmp_imm(RAX, '-' as i32);
+    asm.jcc(Equal, negative);
+    digit(asm, skip, false);
+    asm.mov_imm(R9, 1);
+    asm.mov_imm(R8, 0);
+    asm.jmp(number);
+    asm.bind(negative);
+    asm.mov_imm(R9, -1i64 as u64);
+    asm.mov_imm(R8, 0);
+    asm.call(sys.read_byte);
+    digit(asm, done, false);
+    asm.bind(number);
+    asm.mov_imm(RAX, 10);
+    asm.imul(R8, RAX);
+    asm.add(R8, RCX);
+    asm.call(sys.read_byte);
+    digit(asm, number, true);
+    asm.bind(done);
+    asm.mov(RAX, R8);
+    asm.imul(RAX, R9);
+    asm.ret();
+    asm.bind(eof);
+    asm.ret();
+
+    // read_char decodes one UTF-8 sequence.
+    let done = asm.new_label();
+    let two = asm.new_label();
+    let three = asm.new_label();
+    let continuation = asm.new_label();
+    asm.bind(rt.read_char);
+    asm.call(sys.read_byte);
+    asm.cmp_imm(RAX, 0x80);
+    asm.jcc(Below, done);
+    asm.cmp_imm(RAX, -1);
+    asm.jcc(Equal, done);
+    asm.mov(R8, RAX);
+    asm.cmp_imm(RAX, 0xe0);
+    asm.jcc(Below, two);
+    asm.cmp_imm(RAX, 0xf0);
+    asm.jcc(Below, three);
+    asm.and_imm(R8, 0x07);
+    asm.mov_imm(R9, 3);
+    asm.jmp(continuation);
+    asm.bind(three);
+    asm.and_imm(R8, 0x0f);
+    asm.mov_imm(R9, 2);
+    asm.jmp(continuation);
+    asm.bind(two);
+    asm.and_imm(R8, 0x1f);
+    asm.mov_imm(R9, 1);
+    asm.bind(continuation);
+    asm.call(sys.read_byte);
+    asm.and_imm(RAX, 0x3f);
+    asm.shift(R8, 6);
+    asm.add(R8, RAX);
+    asm.sub_imm(R9, 1);
+    asm.jcc(NotEqual, continuation);
+    asm.mov(RAX, R8);
+    asm.bind(done);
+    asm.ret();
+
+    exit(asm, sys, sys.exit, 0);
+    fail(asm, sys, sys.escape, "aheui: the counter left the map\n");
+    fail(asm, sys, sys.invalid_char, "aheui: invalid character\n");
+    fail(asm, sys, rt.overflow, "aheui: storage overflow\n");
+    fail(asm, sys, rt.divide_by_zero, "aheui: division by zero\n");
+}
+
+/// `rcx += len - 1`, the offset of the last value from the offset of the
+/// first one in rcx, before wrapping around. Clobbers rdx.
+fn last_offset(asm: &mut Assembler) {
+    asm.load(RDX, RBX, LEN);
+    asm.add(RCX, RDX);
+    asm.sub_imm(RCX, 1);
+}
+
+/// `rcx = base + (rcx & mask) * 8`, the address of the value at the offset
+/// in rcx. Clobbers rdx.
+fn element(asm: &mut Assembler) {
+    asm.load(RDX, RBX, MASK);
+    asm.and(RCX, RDX);
+    asm.shift(RCX, 3);
+    asm.load(RDX, RBX, BASE);
+    asm.add(RCX, RDX);
+}
+
+fn exit(asm: &mut Assembler, sys: &System, label: Label, status: u64) {
+    asm.bind(label);
+    asm.call(sys.flush);
+    syscall(asm, 60, status); // exit(status)
+    asm.syscall();
+}
+
+/// Writes `message` to the standard error and exits with status 1.
+fn fail(asm: &mut Assembler, sys: &System, label: Label, message: &str) {
+    assert!(message.len() <= (NUMBER_END - NUMBER) as uint);
+    asm.bind(label);
+    asm.call(sys.flush);
+    for (i, b) in message.bytes().enumerate() {
+        asm.mov_imm(RAX, b as u64);
+        asm.store_byte(R15, NUMBER + i as i32, RAX);
+    }
+    syscall(asm, 1, 2); // write(2, number, len)
+    asm.lea(RSI, R15, NUMBER);
+    asm.mov_imm(RDX, message.len() as u64);
+    asm.syscall();
+    syscall(asm, 60, 1); // exit(1)
+    asm.syscall();
+}
@@ -0,0 +1,144 @@
+//! State space of a `Source`, explored without running it.
+//!
+//! A state is everything the movement of the interpreter depends on: the
+//! counter, the direction and the last move. Storage contents only decide
+//! whether an operation reflects, so every state has a regular successor and,
+//! when its operation can underflow, a reflected one.
+
+use std::collections::HashMap;
+
+use super::{Source, Instruction, InterpreterDirection, Down, HaltOperation};
+use super::{PushIntegerInputOperation, PushCharInputOperation};
+
+#[deriving(Clone, PartialEq, Eq, Hash, Show)]
+pub struct State {
+    /// Counter in map coordinates, including the two rows and columns of walls.
+    pub counter: (int, int),
+    pub direction: InterpreterDirection,
+    pub last_move: (int, int),
+}
+
+impl State {
+    pub fn start() -> State {
+        State { counter: (2, 2), direction: Down, last_move: (1, 0) }
+    }
+
+    /// Position in source coordinates, as `Interpreter::counter` reports it.
+    pub fn position(&self) -> (int, int) {
+        match self.counter {
+            (row, col) => (row - 2, col - 2)
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Edge {
+    Goto(uint),
+    /// The instruction is `HaltOperation`.
+    Halt,
+    /// The counter leaves the map and the interpreter would fail.
+    Escape,
+}
+
+pub struct Node {
+    pub state: State,
+    pub instruction: Instruction,
+    pub next: Edge,
+    /// Successor when the operation underflows; `None` if it never does.
+    pub reflected: Option<Edge>,
+}
+
+pub struct Graph {
+    /// Reachable states in discovery order; the first one is the start.
+    pub nodes: Vec<Node>,
+}
+
+impl Graph {
+    pub fn from_source(source: &Source) -> Graph {
+        let mut graph = Graph { nodes: Vec::new() };
+        let mut indices: HashMap<State, uint> = HashMap::new();
+        let mut queue: Vec<uint> = Vec::new();
+
+        let start = State::start();
+        let _ = graph.intern(source, &mut indices, &mut queue, start);
+        while !queue.is_empty() {
+            let idx = queue.remove(0).unwrap();
+            let (state, instruction) = {
+                let node = graph.nodes.get(idx);
+                (node.state, node.instruction)
+            };
+            let (next, reflected) = match instruction.operation {
+                HaltOperation => (Halt, None),
+                operation => {
+                    let next = graph.follow(source, &mut indices, &mut queue, &instruction, state, false);
+                    let reflected = if operation.can_reflect() {
+                        Some(graph.follow(source, &mut indices, &mut queue, &instruction, state, true))
+                    } else {
+                        None
+                    };
+                    (next, reflected)
+                }
+            };
+            let node = graph.nodes.get_mut(idx);
+            node.next = next;
+            node.reflected = reflected;
+        }
+
+        graph
+    }
+
+    fn follow(&mut self, source: &Source, indices: &mut HashMap<State, uint>, queue: &mut Vec<uint>,
+              instruction: &Instruction, state: State, branch: bool) -> Edge {
+        let (counter, direction, last_move) = instruction.advance(state.counter, state.direction, state.last_move, branch);
+        let next = State { counter: counter, direction: direction, last_move: last_move };
+        match self.intern(source, indices, queue, next) {
+            Some(idx) => Goto(idx),
+            None => Escape,
+        }
+    }
+
+    fn intern(&mut self, source: &Source, indices: &mut HashMap<State, uint>, queue: &mut Vec<uint>,
+              state: State) -> Option<uint> {
+        match indices.find(&state) {
+            Some(idx) => { return Some(*idx); }
+            None => { }
+        }
+        match source._try_get(state.counter) {
+            Some(instruction) => {
+                let idx = self.nodes.len();
+                self.nodes.push(Node { state: state, instruction: instruction, next: Halt, reflected: None });
+                indices.insert(state, idx);
+                queue.push(idx);
+                Some(idx)
+            }
+            None => None,
+        }
+    }
+
+    pub fn len(&self) -> uint {
+        self.nodes.len()
+    }
+
+    /// Successors of a node, regular one first.
+    pub fn successors(&self, idx: uint) -> Vec<uint> {
+        let node = self.nodes.get(idx);
+        let mut result = Vec::new();
+        match node.next {
+            Goto(next) => { result.push(next); }
+            _ => { }
+        }
+        match node.reflected {
+            Some(Goto(next)) if !result.contains(&next) => { result.push(next); }
+            _ => { }
+        }
+        result
+    }
+
+    /// Whether some reachable state reads from the input.
+    pub fn reads_input(&self) -> bool {
+        self.nodes.iter().any(|node| match node.instruction.operation {
+            PushIntegerInputOperation | PushCharInputOperation => true,
+            _ => false,
+        })
+    }
+}
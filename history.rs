@@ -0,0 +1,176 @@
+//! Undo log for stepping an `Interpreter` backwards.
+//!
+//! Every instruction executed while the history is enabled leaves a `Step`
+//! with the position it started from and the storage edits it made. Every
+//! `interval` steps the log is replaced by a `Checkpoint` holding copies of
+//! all storages; only `limit` checkpoints are kept, which bounds both the
+//! memory used and how far back the interpreter can go. Stepping back past a
+//! checkpoint restores the one before it and executes the program forward
+//! again, with its output discarded.
+//!
+//! Values read from the input are remembered and read again when the same
+//! steps are executed after stepping back. Output is never taken back.
+
+use super::{InterpreterDirection, Storage};
+
+/// Steps between checkpoints the debugger uses.
+pub static DEFAULT_INTERVAL: uint = 10000;
+/// Checkpoints the debugger keeps, so it can go back about a million steps.
+pub static DEFAULT_LIMIT: uint = 100;
+
+/// A change one instruction made, undone in reverse order.
+pub enum Edit {
+    /// A value was picked from the storage at an index.
+    Picked(uint, int),
+    /// A value was `put` to the storage at an index.
+    Put(uint),
+    /// The two values next to be picked from the storage at an index were
+    /// exchanged.
+    Swapped(uint),
+    /// A value was read from the input.
+    Read(int),
+}
+
+impl Edit {
+    /// Reverts the edit on the storages. `Read` leaves them alone.
+    pub fn undo(&self, storages: &mut Vec<Box<Storage>>) {
+        match *self {
+            Picked(index, v) => { storages.get_mut(index).rput(v); }
+            Put(index) => { let _ = storages.get_mut(index).unput(); }
+            Swapped(index) => { let _ = storages.get_mut(index).swap(); }
+            Read(_) => { }
+        }
+    }
+}
+
+/// The state an instruction started from and what it changed.
+pub struct Step {
+    pub counter: (int, int),
+    pub direction: InterpreterDirection,
+    pub last_move: (int, int),
+    pub storage_index: uint,
+    pub reflected: bool,
+    pub underflowed: bool,
+    pub edits: Vec<Edit>,
+}
+
+/// Full copy of the interpreter state between two steps.
+#[deriving(Clone)]
+pub struct Checkpoint {
+    pub counter: (int, int),
+    pub direction: InterpreterDirection,
+    pub last_move: (int, int),
+    pub storage_index: uint,
+    pub reflected: bool,
+    pub underflowed: bool,
+    /// `Storage::values` of every storage.
+    pub storages: Vec<Vec<int>>,
+}
+
+struct Segment {
+    checkpoint: Checkpoint,
+    /// Values read from the input since the checkpoint.
+    inputs: Vec<int>,
+}
+
+pub struct History {
+    interval: uint,
+    limit: uint,
+    /// Oldest first. The log starts at the last one.
+    segments: Vec<Segment>,
+    log: Vec<Step>,
+    /// Input values to read again, the next one last.
+    replay: Vec<int>,
+}
+
+impl History {
+    /// Starts a history at `checkpoint`. `interval` and `limit` must not be 0.
+    pub fn new(interval: uint, limit: uint, checkpoint: Checkpoint) -> History {
+        assert!(interval > 0 && limit > 0);
+        History {
+            interval: interval,
+            limit: limit,
+            segments: vec!(Segment { checkpoint: checkpoint, inputs: Vec::new() }),
+            log: Vec::new(),
+            replay: Vec::new(),
+        }
+    }
+
+    /// Forgets every step and checkpoint and starts again at `checkpoint`.
+    pub fn reset(&mut self, checkpoint: Checkpoint) {
+        self.segments = vec!(Segment { checkpoint: checkpoint, inputs: Vec::new() });
+        self.log.clear();
+        self.replay.clear();
+    }
+
+    pub fn begin(&mut self, step: Step) {
+        self.log.push(step);
+    }
+
+    pub fn record(&mut self, edit: Edit) {
+        match edit {
+            Read(v) => { self.segments.mut_last().unwrap().inputs.push(v); }
+            _ => { }
+        }
+        match self.log.mut_last() {
+            Some(step) => { step.edits.push(edit); }
+            None => fail!("edit recorded outside of a step"),
+        }
+    }
+
+    /// Whether the log is full and the next state must be a checkpoint.
+    pub fn checkpoint_due(&self) -> bool {
+        self.log.len() >= self.interval
+    }
+
+    pub fn push_checkpoint(&mut self, checkpoint: Checkpoint) {
+        self.log.clear();
+        self.segments.push(Segment { checkpoint: checkpoint, inputs: Vec::new() });
+        if self.segments.len() > self.limit {
+            let _ = self.segments.remove(0);
+        }
+    }
+
+    /// Next input value to read again, if the interpreter stepped back over
+    /// some input.
+    pub fn replayed_input(&mut self) -> Option<int> {
+        self.replay.pop()
+    }
+
+    /// Removes the last step from the log. Values it read are set to be read
+    /// again.
+    pub fn pop(&mut self) -> Option<Step> {
+        let step = self.log.pop();
+        match step {
+            Some(ref step) => {
+                for edit in step.edits.iter().rev() {
+                    match *edit {
+                        Read(v) => {
+                            let _ = self.segments.mut_last().unwrap().inputs.pop();
+                            self.replay.push(v);
+                        }
+                        _ => { }
+                    }
+                }
+            }
+            None => { }
+        }
+        step
+    }
+
+    /// Drops the last checkpoint when the log is empty, returning the one
+    /// before it and the number of steps to execute from there to arrive one
+    /// step before the dropped one.
+    pub fn rewind(&mut self) -> Option<(Checkpoint, uint)> {
+        if !self.log.is_empty() || self.segments.len() < 2 {
+            return None;
+        }
+        let _ = self.segments.pop();
+        let segment = self.segments.mut_last().unwrap();
+        for v in segment.inputs.iter().rev() {
+            self.replay.push(*v);
+        }
+        segment.inputs.clear();
+        Some((segment.checkpoint.clone(), self.interval - 1))
+    }
+}
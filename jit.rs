@@ -0,0 +1,263 @@
+//! Native code generation for x86-64 Linux.
+//!
+//! Each state of the program's `Graph` becomes a block of machine code in an
+//! executable mapping, with the operations `elf` generates: the storages are
+//! ring buffers in host memory, laid out as in the executables, and the
+//! movement between states is compiled into direct jumps. Nothing is decoded
+//! at run time. Only input and output call back into the interpreter, and a
+//! full storage calls back to grow its buffer.
+
+use std::mem;
+use std::ptr;
+use std::os::{MemoryMap, MapReadable, MapWritable, MapExecutable};
+
+use super::{Source, Interpreter, new_storage};
+use graph::{Graph, Goto, Halt, Escape};
+use x86::{Assembler, Label, RAX, RDX, RBX, RSP, RSI, RDI, R8, R9, R10, R11, R12, R15};
+use elf;
+use elf::{Runtime, DESCRIPTOR_SIZE, BASE, HEAD, LEN, MASK, QUEUE};
+
+/// Returned by the compiled code when the counter leaves the map. Otherwise it
+/// returns the index of the halting node.
+static ESCAPED: u64 = 0xffff_ffff_ffff_ffff;
+static DIVIDED_BY_ZERO: u64 = 0xffff_ffff_ffff_fffe;
+
+/// Values a storage buffer holds at first; it doubles when full.
+static INITIAL_CAPACITY: uint = 64;
+
+/// Storages of a run. `words` holds a descriptor per storage, then the
+/// address of the selected one, which is read on entry and written on exit.
+struct Machine {
+    interpreter: *mut Interpreter,
+    words: Vec<u64>,
+    buffers: Vec<Vec<int>>,
+}
+
+static SELECTED: i32 = 28 * DESCRIPTOR_SIZE;
+
+impl Machine {
+    fn new(interpreter: &mut Interpreter) -> Machine {
+        let mut machine = Machine {
+            interpreter: interpreter as *mut Interpreter,
+            words: Vec::from_elem(SELECTED as uint / 8 + 1, 0u64),
+            buffers: Vec::from_fn(28, |_| Vec::new()),
+        };
+        for index in range(0, 28) {
+            let values = interpreter.storage_at(index).values();
+            let mut capacity = INITIAL_CAPACITY;
+            while capacity < values.len() {
+                capacity *= 2;
+            }
+            machine.load(index, values.as_slice(), capacity);
+        }
+        let selected = machine.descriptor(interpreter.storage_index);
+        *machine.words.get_mut(SELECTED as uint / 8) = selected;
+        machine
+    }
+
+    fn descriptor(&self, index: uint) -> u64 {
+        self.words.as_ptr() as u64 + (index as i32 * DESCRIPTOR_SIZE) as u64
+    }
+
+    fn word(&self, index: uint, field: i32) -> u64 {
+        *self.words.get(((index as i32 * DESCRIPTOR_SIZE + field) / 8) as uint)
+    }
+
+    fn set_word(&mut self, index: uint, field: i32, value: u64) {
+        *self.words.get_mut(((index as i32 * DESCRIPTOR_SIZE + field) / 8) as uint) = value;
+    }
+
+    /// Puts `values` in a new buffer of `capacity` values, a power of two.
+    fn load(&mut self, index: uint, values: &[int], capacity: uint) {
+        let mut buffer = Vec::from_elem(capacity, 0);
+        for (i, v) in values.iter().enumerate() {
+            *buffer.get_mut(i) = *v;
+        }
+        self.set_word(index, BASE, buffer.as_ptr() as u64);
+        self.set_word(index, HEAD, 0);
+        self.set_word(index, LEN, values.len() as u64);
+        self.set_word(index, MASK, (capacity - 1) as u64);
+        self.set_word(index, QUEUE, if index == 21 || index == 27 { 1 } else { 0 });
+        *self.buffers.get_mut(index) = buffer;
+    }
+
+    /// Values of a storage, in the order `Storage::values` gives them.
+    fn values(&self, index: uint) -> Vec<int> {
+        let head = self.word(index, HEAD) as uint;
+        let mask = self.word(index, MASK) as uint;
+        let buffer = self.buffers.get(index);
+        Vec::from_fn(self.word(index, LEN) as uint, |i| *buffer.get((head + i) & mask))
+    }
+
+    /// Gives the storages and the selection back to the interpreter.
+    fn store(&self, interpreter: &mut Interpreter) {
+        for index in range(0, 28) {
+            let mut storage = new_storage(index);
+            for v in self.values(index).iter() {
+                storage.put(*v);
+            }
+            *interpreter.storages.get_mut(index) = storage;
+        }
+        let selected = *self.words.get(SELECTED as uint / 8) - self.descriptor(0);
+        interpreter.storage_index = (selected / DESCRIPTOR_SIZE as u64) as uint;
+    }
+}
+
+extern "C" fn grow(machine: *mut Machine, descriptor: u64) {
+    let machine = unsafe { &mut *machine };
+    let index = ((descriptor - machine.descriptor(0)) / DESCRIPTOR_SIZE as u64) as uint;
+    let values = machine.values(index);
+    let capacity = 2 * (machine.word(index, MASK) as uint + 1);
+    machine.load(index, values.as_slice(), capacity);
+}
+
+extern "C" fn read_int(machine: *mut Machine) -> i64 {
+    unsafe { (*(*machine).interpreter).read_integer() as i64 }
+}
+
+extern "C" fn read_char(machine: *mut Machine) -> i64 {
+    unsafe { (*(*machine).interpreter).read_character() as i64 }
+}
+
+extern "C" fn print_int(machine: *mut Machine, v: i64) {
+    unsafe { (*(*machine).interpreter).print_integer(v as int) }
+}
+
+extern "C" fn print_char(machine: *mut Machine, v: i64) {
+    unsafe { (*(*machine).interpreter).print_character(v as int) }
+}
+
+pub struct Compiled {
+    graph: Graph,
+    map: MemoryMap,
+}
+
+impl Compiled {
+    pub fn new(source: &Source) -> Compiled {
+        let graph = Graph::from_source(source);
+        let code = compile(&graph);
+        let map = match MemoryMap::new(code.len(), &[MapReadable, MapWritable, MapExecutable]) {
+            Ok(map) => map,
+            Err(e) => fail!("aheui: cannot map memory for compiled code: {}", e),
+        };
+        unsafe {
+            ptr::copy_nonoverlapping_memory(map.data, code.as_ptr(), code.len());
+        }
+        Compiled { graph: graph, map: map }
+    }
+
+    /// Runs the program from its start with the storages and streams of
+    /// `interpreter`. Its counter is left on the halting cell, as `execute`
+    /// leaves it.
+    pub fn run(&self, interpreter: &mut Interpreter) {
+        let entry: extern "C" fn(*mut Machine, *mut u64) -> u64 = unsafe { mem::transmute(self.map.data) };
+        let mut machine = Machine::new(interpreter);
+        let words = machine.words.as_mut_ptr();
+        let result = entry(&mut machine as *mut Machine, words);
+        machine.store(interpreter);
+        if result == ESCAPED {
+            fail!("aheui: the counter left the map");
+        }
+        if result == DIVIDED_BY_ZERO {
+            fail!("aheui: division by zero");
+        }
+        let state = self.graph.nodes.get(result as uint).state;
+        interpreter.counter = state.counter;
+        interpreter.direction = state.direction;
+        interpreter.last_move = state.last_move;
+    }
+}
+
+/// Compiles the source of `interpreter` and runs it from the start.
+pub fn execute(interpreter: &mut Interpreter) {
+    let compiled = Compiled::new(&interpreter.source);
+    compiled.run(interpreter);
+}
+
+fn compile(graph: &Graph) -> Vec<u8> {
+    let mut asm = Assembler::new();
+    let rt = Runtime::new(&mut asm);
+    let labels = Vec::from_fn(graph.len(), |_| asm.new_label());
+    let escape = asm.new_label();
+    let exit = asm.new_label();
+
+    // r12 keeps the machine for callbacks, r15 the descriptors and rbx the
+    // selected one. Pushing the three also aligns the stack to 16 bytes.
+    asm.push(RBX);
+    asm.push(R12);
+    asm.push(R15);
+    asm.mov(R12, RDI);
+    asm.mov(R15, RSI);
+    asm.load(RBX, R15, SELECTED);
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        asm.bind(*labels.get(idx));
+        if node.next == Halt {
+            asm.mov_imm(RAX, idx as u64);
+            asm.jmp(exit);
+            continue;
+        }
+        let reflect = match node.reflected {
+            Some(Goto(target)) => *labels.get(target),
+            _ => escape,
+        };
+        elf::compile_operation(&mut asm, &rt, &node.instruction, reflect);
+        match node.next {
+            Goto(next) if next == idx + 1 => { }
+            Goto(next) => { asm.jmp(*labels.get(next)); }
+            Halt | Escape => { asm.jmp(escape); }
+        }
+    }
+    asm.bind(rt.divide_by_zero);
+    asm.mov_imm(RAX, DIVIDED_BY_ZERO);
+    asm.jmp(exit);
+    asm.bind(escape);
+    asm.mov_imm(RAX, ESCAPED);
+    asm.bind(exit);
+    asm.store(R15, SELECTED, RBX);
+    asm.pop(R15);
+    asm.pop(R12);
+    asm.pop(RBX);
+    asm.ret();
+
+    elf::compile_storages(&mut asm, &rt);
+    compile_callbacks(&mut asm, &rt);
+    asm.finish()
+}
+
+/// Binds the routines of `rt` which call back into Rust.
+fn compile_callbacks(asm: &mut Assembler, rt: &Runtime) {
+    callback(asm, rt.read_int, read_int as uint as u64);
+    callback(asm, rt.read_char, read_char as uint as u64);
+    callback(asm, rt.print_int, print_int as uint as u64);
+    callback(asm, rt.print_char, print_char as uint as u64);
+
+    // Storage routines may only clobber rax and rcx, and the value being put
+    // is in rax. Eight pushes keep the stack aligned: `reserve` was called
+    // from a routine called from the compiled code.
+    let saved = [RAX, RDX, RSI, RDI, R8, R9, R10, R11];
+    asm.bind(rt.overflow);
+    for reg in saved.iter() {
+        asm.push(*reg);
+    }
+    asm.mov(RDI, R12);
+    asm.mov(RSI, RBX);
+    asm.mov_imm(RAX, grow as uint as u64);
+    asm.call_reg(RAX);
+    for reg in saved.iter().rev() {
+        asm.pop(*reg);
+    }
+    asm.ret();
+}
+
+/// Binds `label` to a call of `f` with the machine and rax, from a routine
+/// called from the compiled code.
+fn callback(asm: &mut Assembler, label: Label, f: u64) {
+    asm.bind(label);
+    asm.sub_imm(RSP, 8);
+    asm.mov(RDI, R12);
+    asm.mov(RSI, RAX);
+    asm.mov_imm(RAX, f);
+    asm.call_reg(RAX);
+    asm.add_imm(RSP, 8);
+    asm.ret();
+}
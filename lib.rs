@@ -4,6 +4,33 @@
 #![feature(macro_rules)]
 
 extern crate hangeul;
+extern crate serialize;
+
+pub mod graph;
+pub mod transpile;
+pub mod x86;
+#[cfg(target_os = "linux", target_arch = "x86_64")]
+pub mod jit;
+pub mod elf;
+pub mod debugger;
+pub mod trace;
+pub mod history;
+pub mod snapshot;
+pub mod visualize;
+pub mod profile;
+pub mod coverage;
+pub mod observer;
+pub mod dap;
+pub mod reachability;
+pub mod depth;
+pub mod lint;
+pub mod cfg;
+pub mod symbolic;
+pub mod usage;
+pub mod loops;
+pub mod strip;
+pub mod stats;
+pub mod disasm;
 
 #[macro_export]
 macro_rules! printerr(
@@ -12,6 +39,19 @@ macro_rules! printerr(
     );
 )
 
+/// Calls a method of every observer of an interpreter. The arguments are
+/// only evaluated when there are observers.
+macro_rules! notify(
+    ($it:expr, $method:ident($($arg:expr),*)) => (
+        if !$it.observers.is_empty() {
+            for observer in $it.observers.mut_iter() {
+                observer.$method($($arg),*);
+            }
+        }
+    );
+)
+
+#[deriving(Clone, PartialEq, Eq, Hash, Show)]
 pub enum InterpreterDirection {
     Down,
     Up,
@@ -19,6 +59,27 @@ pub enum InterpreterDirection {
     Left,
 }
 
+impl InterpreterDirection {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Down => "down",
+            Up => "up",
+            Right => "right",
+            Left => "left",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<InterpreterDirection> {
+        match name {
+            "down" => Some(Down),
+            "up" => Some(Up),
+            "right" => Some(Right),
+            "left" => Some(Left),
+            _ => None,
+        }
+    }
+}
+
 pub enum InstructionData {
     Hangeul(hangeul::ConcreteSyllable),
     Character(char),
@@ -61,6 +122,85 @@ pub enum InstructionOperation {
     HaltOperation,
 }
 
+/// An `InstructionOperation` without its operands.
+#[deriving(Clone, PartialEq, Eq, Hash, Show)]
+pub enum OperationKind {
+    NopKind,
+    PushKind,
+    DupKind,
+    InputIntKind,
+    InputCharKind,
+    BinaryKind,
+    PopKind,
+    PrintIntKind,
+    PrintCharKind,
+    SwapKind,
+    SelectKind,
+    MoveKind,
+    CompareKind,
+    BranchKind,
+    HaltKind,
+}
+
+impl OperationKind {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            NopKind => "nop",
+            PushKind => "push",
+            DupKind => "dup",
+            InputIntKind => "input-int",
+            InputCharKind => "input-char",
+            BinaryKind => "binary",
+            PopKind => "pop",
+            PrintIntKind => "print-int",
+            PrintCharKind => "print-char",
+            SwapKind => "swap",
+            SelectKind => "select",
+            MoveKind => "move",
+            CompareKind => "compare",
+            BranchKind => "branch",
+            HaltKind => "halt",
+        }
+    }
+}
+
+impl InstructionOperation {
+    pub fn kind(&self) -> OperationKind {
+        match *self {
+            NoOperation => NopKind,
+            PushConstantOperation(_) => PushKind,
+            PushDuplicationOperation => DupKind,
+            PushIntegerInputOperation => InputIntKind,
+            PushCharInputOperation => InputCharKind,
+            BinaryOperation(_) => BinaryKind,
+            PopOperation => PopKind,
+            PrintIntegerOperation => PrintIntKind,
+            PrintCharOperation => PrintCharKind,
+            SwapOperation => SwapKind,
+            ChangeStorageOperation(_) => SelectKind,
+            MoveToStorageOperation(_) => MoveKind,
+            CompareOperation => CompareKind,
+            BranchOperation => BranchKind,
+            HaltOperation => HaltKind,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.kind().name()
+    }
+
+    /// Whether the operation reverses the movement when the storage does not
+    /// hold enough values.
+    pub fn can_reflect(&self) -> bool {
+        match *self {
+            BinaryOperation(_) | PrintIntegerOperation | PrintCharOperation | PopOperation
+                | PushDuplicationOperation | SwapOperation | MoveToStorageOperation(_)
+                | CompareOperation | BranchOperation => true,
+            _ => false,
+        }
+    }
+}
+
 fn operation_digeut(v1: int, v2: int) -> int { v2 + v1 }
 fn operation_ssang_digeut(v1: int, v2: int) -> int { v2 * v1 }
 fn operation_tieut(v1: int, v2: int) -> int { v2 - v1 }
@@ -146,6 +286,144 @@ impl Instruction {
             _ => { assert!(false); fail!("") }
         }
     }
+
+    pub fn char(&self) -> Option<char> {
+        match self.data {
+            Hangeul(syllable) => syllable.char(),
+            Character(c) => Some(c),
+            Virtual => None,
+        }
+    }
+
+    /// Whether the cell holds a syllable, which is an instruction even when
+    /// it does nothing.
+    pub fn is_hangeul(&self) -> bool {
+        match self.data {
+            Hangeul(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        match self.data {
+            Virtual => true,
+            _ => false,
+        }
+    }
+
+    pub fn operation(&self) -> InstructionOperation {
+        self.operation
+    }
+
+    /// Name of the arithmetic of a `BinaryOperation`: add, mul, sub, div or mod.
+    pub fn binary_operator(&self) -> Option<&'static str> {
+        match self.operation {
+            BinaryOperation(_) => Some(match self.hangeul().initial() {
+                hangeul::InitialDigeut => "add",
+                hangeul::InitialSsangDigeut => "mul",
+                hangeul::InitialTieut => "sub",
+                hangeul::InitialNieun => "div",
+                _ => "mod",
+            }),
+            _ => None,
+        }
+    }
+
+    /// Operation with its argument, such as `push 4`, `add` or `select 21`.
+    pub fn mnemonic(&self) -> String {
+        match self.operation {
+            PushConstantOperation(v) => format!("push {}", v),
+            BinaryOperation(_) => String::from_str(self.binary_operator().unwrap()),
+            ChangeStorageOperation(index) => format!("select {}", index),
+            MoveToStorageOperation(index) => format!("move {}", index),
+            operation => String::from_str(operation.name()),
+        }
+    }
+
+    /// Computes the counter, direction and last move following this
+    /// instruction. `branch` reverses the movement, as an operation lacking
+    /// values in its storage does.
+    pub fn advance(&self, counter: (int, int), direction: InterpreterDirection, last_move: (int, int), branch: bool)
+            -> ((int, int), InterpreterDirection, (int, int)) {
+        let mut counter = counter;
+        let mut direction_move = match self.move {
+            RegularMovement(new_direction, row, col) => {
+                (new_direction, (row, col))
+            },
+            AllowHorizontalMovement => match direction {
+                Right | Left => (direction, last_move),
+                Up => (Down, (1, 0)),
+                Down => (Up, (-1, 0)),
+            },
+            AllowVerticalMovement =>  match direction {
+                Up | Down => (direction, last_move),
+                Right => (Left, (0, -1)),
+                Left => (Right, (1, 0)),
+            },
+            DisallowMovement => {
+                (
+                    match direction {
+                        Right => Left,
+                        Left => Right,
+                        Up => Down,
+                        Down => Up,
+                    },
+                    match last_move {
+                        (row, col) => (-row, -col)
+                    }
+                )
+            },
+            KeepCurrentMovement => {
+                (direction, last_move)
+            },
+            WallMovement(wall_direction, value) => {
+                match (direction, wall_direction) {
+                    (Up, Up) => {
+                        counter = match counter {
+                            (_, col) => (value + 1, col),
+                        }
+                    }
+                    (Down, Down) => {
+                        counter = match counter {
+                            (_, col) => (value - 1, col),
+                        }
+                    }
+                    (Right, Right) => {
+                        counter = match counter {
+                            (row, _) => (row, value - 1),
+                        }
+                    }
+                    (Left, Left) => {
+                        counter = match counter {
+                            (row, _) => (row, value + 1),
+                        }
+                    }
+                    _ => { }
+                }
+                (direction, last_move)
+            }
+        };
+
+        if branch {
+            direction_move = match direction_move {
+                (direction, (row, col)) => (
+                    match direction {
+                        Right => Left,
+                        Left => Right,
+                        Up => Down,
+                        Down => Up,
+                    },
+                    (-row, -col)
+                )
+            }
+        }
+
+        match (counter, direction_move) {
+            ((row, col), (direction, (row_diff, col_diff))) => {
+                ((row + row_diff, col + col_diff), direction, (row_diff, col_diff))
+            }
+        }
+    }
 }
 
 pub struct Source {
@@ -219,11 +497,65 @@ impl Source {
         }
     }
 
+    fn _try_get(&self, pos: (int, int)) -> Option<Instruction> {
+        match pos {
+            (ridx, cidx) => {
+                if ridx < 0 || ridx >= self.map.len() as int || cidx < 0 {
+                    None
+                } else {
+                    Some(self._get(pos))
+                }
+            }
+        }
+    }
+
     pub fn get(&self, pos: (int, int)) -> Instruction {
         match pos {
             (ridx, cidx) => self._get((ridx + 2, cidx + 2))
         }
     }
+
+    /// Number of rows of the program.
+    pub fn height(&self) -> uint {
+        self.map.len() - 4
+    }
+
+    /// Number of cells in a row of the program.
+    pub fn row_len(&self, row: uint) -> uint {
+        self.map.get(row + 2).len() - 2
+    }
+
+    /// Whether the counter can be at `pos`, in program coordinates: on a
+    /// cell of the program or on the two cells of walls around it.
+    pub fn contains(&self, pos: (int, int)) -> bool {
+        let (row, col) = pos;
+        let (row, col) = (row + 2, col + 2);
+        row >= 0 && row < self.map.len() as int && col >= 0 && col < self.map.get(row as uint).len() as int + 2
+    }
+
+    /// 64-bit FNV-1a hash of the program text, with rows ended by newlines.
+    pub fn hash(&self) -> u64 {
+        let mut hash = 0xcbf29ce484222325u64;
+        let mut buf = [0u8, ..4];
+        for row in range(0, self.height()) {
+            for col in range(0, self.row_len(row)) {
+                let c = self.get((row as int, col as int)).char().unwrap_or(' ');
+                let len = c.encode_utf8(buf.as_mut_slice());
+                for b in buf.slice_to(len).iter() {
+                    hash = (hash ^ *b as u64) * 0x100000001b3;
+                }
+            }
+            hash = (hash ^ '\n' as u64) * 0x100000001b3;
+        }
+        hash
+    }
+}
+
+impl Clone for Source {
+    fn clone(&self) -> Source {
+        // Instructions are plain data, copied as they are.
+        Source { map: self.map.iter().map(|row| row.iter().map(|instruction| *instruction).collect()).collect() }
+    }
 }
 
 pub trait Storage {
@@ -232,6 +564,11 @@ pub trait Storage {
     fn rput(&mut self, data: int);
     fn pick(&mut self) -> Option<int>;
     fn peek(&self) -> Option<int>;
+    /// Removes the value the last `put` added.
+    fn unput(&mut self) -> Option<int>;
+    /// Values from the bottom of a stack to its top, or from the front of a
+    /// queue to its back.
+    fn values(&self) -> Vec<int>;
     fn swap(&mut self) -> bool {
         if self.len() >= 2 {
             let v1 = self.pick().unwrap();
@@ -245,19 +582,17 @@ pub trait Storage {
     }
 }
 
-pub struct TempStorage {
-    // temp impl to avoid trait - box problem
+pub struct Stack {
     vec: Vec<int>,
-    is_queue: bool,
 }
 
-impl TempStorage {
-    pub fn new(is_queue: bool) -> TempStorage {
-        TempStorage { vec: Vec::new(), is_queue: is_queue }
+impl Stack {
+    pub fn new() -> Stack {
+        Stack { vec: Vec::new() }
     }
 }
 
-impl Storage for TempStorage {
+impl Storage for Stack {
     fn len(&self) -> uint {
         self.vec.len()
     }
@@ -267,143 +602,185 @@ impl Storage for TempStorage {
     }
 
     fn rput(&mut self, data: int) {
-        if self.is_queue {
-            self.vec.unshift(data);
-        } else {
-            self.vec.push(data);
-        }
+        self.vec.push(data);
     }
 
     fn pick(&mut self) -> Option<int> {
-        if self.is_queue {
-            self.vec.shift()
-        } else {
-            self.vec.pop()
-        }
+        self.vec.pop()
     }
 
     fn peek(&self) -> Option<int> {
-        if self.is_queue {
-            if !self.vec.is_empty() {
-                Some(*self.vec.get(0))
-            } else {
-                None
-            }
-        } else {
-            match self.vec.last() {
-                Some(v) => Some(*v),
-                None => None,
-            }
+        match self.vec.last() {
+            Some(v) => Some(*v),
+            None => None,
         }
     }
-}
-
-/*
-pub struct Stack {
-    vec: Vec<int>,
-}
-
-impl Storage for Stack {
-    fn put(&mut self, data: int) {
-        self.vec.push(data);
-    }
-
-    fn rput(&mut self, data: int) {
-        self.vec.push(data);
-    }
 
-    fn pick(&mut self) -> int {
-        self.vec.pop().unwrap()
+    fn unput(&mut self) -> Option<int> {
+        self.vec.pop()
     }
 
-    fn peek(&self) -> int {
-        *self.vec.last().unwrap()
+    fn values(&self) -> Vec<int> {
+        self.vec.clone()
     }
 }
 
-#[test]
-fn test_stack() {
-    let mut stack = Stack { vec: Vec::new() };
-    stack.put(1);
-    stack.put(2);
-    stack.put(3);
-    assert_eq!(3, stack.pick());
-    assert_eq!(2, stack.pick());
-    assert_eq!(1, stack.pick());
+/// Ring buffer; `put` appends to the back, `rput` prepends to the front.
+pub struct Queue {
+    buf: Vec<int>,
+    head: uint,
+    len: uint,
 }
 
-pub  struct Queue {
-    vec: Vec<int>,
+impl Queue {
+    pub fn new() -> Queue {
+        Queue { buf: Vec::from_elem(8, 0), head: 0, len: 0 }
+    }
+
+    fn reserve(&mut self) {
+        let capacity = self.buf.len();
+        if self.len == capacity {
+            let mut buf = Vec::with_capacity(capacity * 2);
+            for i in range(0, self.len) {
+                buf.push(*self.buf.get((self.head + i) % capacity));
+            }
+            buf.grow(capacity, &0);
+            self.buf = buf;
+            self.head = 0;
+        }
+    }
 }
 
 impl Storage for Queue {
+    fn len(&self) -> uint {
+        self.len
+    }
+
     fn put(&mut self, data: int) {
-        self.vec.push(data);
+        self.reserve();
+        let idx = (self.head + self.len) % self.buf.len();
+        *self.buf.get_mut(idx) = data;
+        self.len += 1;
     }
 
     fn rput(&mut self, data: int) {
-        self.vec.insert(0, data);
+        self.reserve();
+        self.head = (self.head + self.buf.len() - 1) % self.buf.len();
+        *self.buf.get_mut(self.head) = data;
+        self.len += 1;
+    }
+
+    fn pick(&mut self) -> Option<int> {
+        if self.len == 0 {
+            return None;
+        }
+        let v = *self.buf.get(self.head);
+        self.head = (self.head + 1) % self.buf.len();
+        self.len -= 1;
+        Some(v)
     }
 
-    fn pick(&mut self) -> int {
-        self.vec.remove(0).unwrap()
+    fn peek(&self) -> Option<int> {
+        if self.len == 0 {
+            None
+        } else {
+            Some(*self.buf.get(self.head))
+        }
     }
 
-    fn peek(&self) -> int {
-        *self.vec.get(0)
+    fn unput(&mut self) -> Option<int> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(*self.buf.get((self.head + self.len) % self.buf.len()))
     }
-}
 
-#[test]
-fn test_queue() {
-    let mut queue = Queue { vec: Vec::new() };
-    queue.put(1);
-    queue.put(2);
-    queue.put(3);
-    assert_eq!(1, queue.pick());
-    assert_eq!(2, queue.pick());
-    queue.put(4);
-    queue.put(5);
-    assert_eq!(3, queue.pick());
-    assert_eq!(4, queue.pick());
-    assert_eq!(5, queue.pick());
+    fn values(&self) -> Vec<int> {
+        Vec::from_fn(self.len, |i| *self.buf.get((self.head + i) % self.buf.len()))
+    }
 }
 
-pub struct Extension {
-    vec: Vec<int>,
+/// Condition checked on the cell the counter arrives at, before the
+/// instruction there is executed.
+pub enum Breakpoint {
+    CellBreakpoint((int, int)),
+    /// The cell, entered while moving in the direction.
+    DirectedCellBreakpoint((int, int), InterpreterDirection),
+    SyllableBreakpoint(char),
+    /// Any syllable whose operation is of this kind.
+    OperationBreakpoint(OperationKind),
 }
 
-impl Storage for Extension {
-    fn put(&mut self, data: int) {
-    }
-
-    fn rput(&mut self, data: int) {
-    }
+/// Condition on the storages checked after every step. Predicates stop the
+/// interpreter when they become true, not while they stay true.
+pub enum Watchpoint {
+    /// Length of the storage at an index.
+    LengthWatchpoint(uint, fn(uint) -> bool),
+    /// Value `peek` returns from the storage at an index.
+    TopWatchpoint(uint, fn(Option<int>) -> bool),
+    /// Any step selecting another storage.
+    StorageSelectionWatchpoint,
+}
 
-    fn pick(&mut self) -> int {
-        0
-    }
+#[deriving(Clone, PartialEq, Show)]
+pub enum StopReason {
+    /// Index of the breakpoint as `add_breakpoint` returned it.
+    BreakpointHit(uint),
+    /// Index of the watchpoint as `add_watchpoint` returned it.
+    WatchpointHit(uint),
+    /// The state recurred, so the program would never halt.
+    InfiniteLoop(loops::Loop),
+}
 
-    fn peek(&self) -> int {
-        0
-    }
+#[deriving(Clone, PartialEq, Show)]
+pub enum RunResult {
+    Halted,
+    Stopped(StopReason),
 }
-*/
 
 pub struct Interpreter {
     source: Source,
-    storages: Vec<TempStorage>, // must be array - fixed size
+    storages: Vec<Box<Storage>>, // must be array - fixed size
     storage_index: uint,
     counter: (int, int),
     last_move: (int, int),
     direction: InterpreterDirection,
-    out: std::io::LineBufferedWriter<std::io::stdio::StdWriter>,
+    input: Box<Buffer>,
+    out: Box<Writer>,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    /// Last value of each watchpoint predicate.
+    watched: Vec<bool>,
+    /// Whether a breakpoint on the current cell was reported, so that the
+    /// next `run` executes the cell instead of stopping again.
+    breakpoint_reported: bool,
+    /// Whether the last instruction reversed the movement.
+    reflected: bool,
+    /// Whether it did so because its storage lacked values, rather than
+    /// because ㅊ picked 0.
+    underflowed: bool,
+    tracer: Option<trace::Tracer>,
+    history: Option<history::History>,
+    profile: Option<profile::Profile>,
+    coverage: Option<coverage::Coverage>,
+    observers: Vec<Box<observer::InterpreterObserver>>,
+    loops: Option<loops::LoopDetector>,
 }
 
 pub static final_draw_counts: [int, ..28] = [0, 2, 4, 4, 2, 5, 5, 3, 5, 7, 9, 9, 7, 9, 9, 8, 4, 4, 6, 2, 4, -1, 3, 4, 3, 4, 4, -1];
+pub static storage_names: [&'static str, ..28] = [
+    "", "ㄱ", "ㄲ", "ㄳ", "ㄴ", "ㄵ", "ㄶ", "ㄷ", "ㄹ", "ㄺ", "ㄻ", "ㄼ", "ㄽ", "ㄾ",
+    "ㄿ", "ㅀ", "ㅁ", "ㅂ", "ㅄ", "ㅅ", "ㅆ", "ㅇ", "ㅈ", "ㅊ", "ㅋ", "ㅌ", "ㅍ", "ㅎ"];
 
 impl Interpreter {
     pub fn new(source: Source) -> Interpreter {
+        Interpreter::with_io(source, box std::io::stdin(), box std::io::stdout())
+    }
+
+    /// Creates an interpreter reading `input` and writing `out` instead of the
+    /// standard streams.
+    pub fn with_io(source: Source, input: Box<Buffer>, out: Box<Writer>) -> Interpreter {
         let mut obj = Interpreter {
             source: source,
             storages: Vec::new(),
@@ -411,21 +788,23 @@ impl Interpreter {
             counter: (2, 2),
             last_move: (1, 0),
             direction: Down,
-            out: std::io::stdio::stdout(),
+            input: input,
+            out: out,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            watched: Vec::new(),
+            breakpoint_reported: false,
+            reflected: false,
+            underflowed: false,
+            tracer: None,
+            history: None,
+            profile: None,
+            coverage: None,
+            observers: Vec::new(),
+            loops: None,
         };
         for x in range(0, hangeul::final0_count) {
-            let storage = match x {
-                21 => {
-                    TempStorage::new(true)
-                }
-                27 => {
-                    TempStorage::new(true)
-                }
-                _ => {
-                    TempStorage::new(false)
-                }
-            };
-            obj.storages.push(storage);
+            obj.storages.push(new_storage(x));
         }
         return obj;
     }
@@ -437,33 +816,63 @@ impl Interpreter {
     }
 
     pub fn storage<'a>(&'a mut self) -> &'a mut Storage {
-         let storage: &mut Storage = self.storages.get_mut(self.storage_index);
+         let storage: &mut Storage = &mut **self.storages.get_mut(self.storage_index);
          storage
     }
 
-    pub fn instruct(&mut self, instruction: &Instruction) -> bool {
+    pub fn storage_at<'a>(&'a self, index: uint) -> &'a Storage {
+        &**self.storages.get(index)
+    }
+
+    pub fn storage_index(&self) -> uint {
+        self.storage_index
+    }
+
+    pub fn direction(&self) -> InterpreterDirection {
+        self.direction
+    }
+
+    pub fn last_move(&self) -> (int, int) {
+        self.last_move
+    }
+
+    pub fn source<'a>(&'a self) -> &'a Source {
+        &self.source
+    }
+
+    /// Instruction under the counter, which `step` executes next.
+    pub fn instruction(&self) -> Instruction {
+        self.source._get(self.counter)
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+
+    /// Applies an operation to the storages and the output, returning whether
+    /// the movement must be reversed. `HaltOperation` does nothing here.
+    pub fn operate(&mut self, operation: InstructionOperation) -> bool {
         let mut branch: bool = false;
-        match instruction.operation {
+        let mut zero = false;
+        match operation {
             PushConstantOperation(v) => {
-                let s = self.storage();
-                s.put(v);
+                self.put(v);
             }
             BinaryOperation(op) => {
-                let s = self.storage();
-                if s.len() >= 2 {
-                    let v1 = s.pick().unwrap();
-                    let v2 = s.pick().unwrap();
+                if self.storage().len() >= 2 {
+                    let v1 = self.pick().unwrap();
+                    let v2 = self.pick().unwrap();
                     let r = op(v1, v2);
-                    s.put(r);
+                    self.put(r);
                 } else {
                     branch = true;
                 }
             }
             PrintIntegerOperation => {
-                let v = self.storage().pick();
+                let v = self.pick();
                 match v {
                     Some(v) => {
-                        let _ = self.out.write_int(v);
+                        self.print_integer(v);
                     }
                     None => {
                         branch = true;
@@ -471,11 +880,10 @@ impl Interpreter {
                 }
             }
             PrintCharOperation => {
-                let v = self.storage().pick();
+                let v = self.pick();
                 match v {
                     Some(v) => {
-                        let c = std::char::from_u32(v as u32);
-                        let _ = self.out.write_char(c.unwrap());
+                        self.print_character(v);
                     }
                     None => {
                         branch = true;
@@ -483,7 +891,7 @@ impl Interpreter {
                 }
             }
             PopOperation => {
-                let v = self.storage().pick();
+                let v = self.pick();
                 match v {
                     None => {
                         branch = true;
@@ -492,11 +900,10 @@ impl Interpreter {
                 }
             }
             PushDuplicationOperation => {
-                let s = self.storage();
-                let v = s.peek();
+                let v = self.storage().peek();
                 match v {
                     Some(v) => {
-                        s.put(v);
+                        self.put(v);
                     }
                     None => {
                         branch = true;
@@ -504,17 +911,24 @@ impl Interpreter {
                 }
             }
             SwapOperation => {
-                let s = self.storage();
-                if !s.swap() {
+                let index = self.storage_index;
+                if self.storage().swap() {
+                    match self.loops {
+                        Some(ref mut loops) => { loops.reload(index, &**self.storages.get(index)); }
+                        None => { }
+                    }
+                    self.record(history::Swapped(index));
+                    notify!(self, on_swap(index));
+                } else {
                     branch = true;
                 }
             }
             MoveToStorageOperation(index) => {
-                let v = self.storage().pick();
+                let v = self.pick();
                 match v {
                     Some(v) => {
                         self.storage_index = index;
-                        self.storage().put(v);
+                        self.put(v);
                     }
                     None => {
                         branch = true;
@@ -525,19 +939,19 @@ impl Interpreter {
                 self.storage_index = index;
             }
             CompareOperation => {
-                let s = self.storage();
-                if s.len() >= 2 {
-                    let v1 = s.pick().unwrap();
-                    let v2 = s.pick().unwrap();
-                    s.put(if v2 >= v1 { 1 } else { 0 });
+                if self.storage().len() >= 2 {
+                    let v1 = self.pick().unwrap();
+                    let v2 = self.pick().unwrap();
+                    self.put(if v2 >= v1 { 1 } else { 0 });
                 } else {
                     branch = true;
                 }
             }
             BranchOperation => {
-                match self.storage().pick() {
+                match self.pick() {
                     Some(v) if v == 0 => {
                         branch = true;
+                        zero = true;
                     }
                     Some(_) => { }
                     None => {
@@ -547,102 +961,148 @@ impl Interpreter {
             }
             NoOperation => { }
             PushIntegerInputOperation => {
-                let mut reader = std::io::stdin();
-                let line = reader.read_line().unwrap();
-                let num: int = from_str(line.as_slice()).unwrap();
-                self.storage().put(num);
+                let num = match self.replayed_input() {
+                    Some(num) => num,
+                    None => self.read_integer(),
+                };
+                self.record(history::Read(num));
+                self.forget_loop();
+                notify!(self, on_input(num));
+                self.put(num);
             }
             PushCharInputOperation => {
-                let mut reader = std::io::stdin();
-                let chr = reader.read_char().unwrap();
-                self.storage().put(chr as int);
-            }
-            HaltOperation => {
-                //pringln!("halt! {:?}", syllable);
-                return true;
+                let chr = match self.replayed_input() {
+                    Some(chr) => chr,
+                    None => self.read_character(),
+                };
+                self.record(history::Read(chr));
+                self.forget_loop();
+                notify!(self, on_input(chr));
+                self.put(chr);
             }
+            HaltOperation => { }
         };
-        let mut direction_move = match instruction.move {
-            RegularMovement(new_direction, row, col) => {
-                (new_direction, (row, col))
-            },
-            AllowHorizontalMovement => match self.direction {
-                Right | Left => (self.direction, self.last_move),
-                Up => (Down, (1, 0)),
-                Down => (Up, (-1, 0)),
-            },
-            AllowVerticalMovement =>  match self.direction {
-                Up | Down => (self.direction, self.last_move),
-                Right => (Left, (0, -1)),
-                Left => (Right, (1, 0)),
-            },
-            DisallowMovement => {
-                (
-                    match self.direction {
-                        Right => Left,
-                        Left => Right,
-                        Up => Down,
-                        Down => Up,
-                    },
-                    match self.last_move {
-                        (row, col) => (-row, -col)
-                    }
-                )
-            },
-            KeepCurrentMovement => {
-                (self.direction, self.last_move)
-            },
-            WallMovement(direction, value) => {
-                match (self.direction, direction) {
-                    (Up, Up) => {
-                        self.counter = match self.counter {
-                            (_, col) => (value + 1, col),
-                        }
-                    }
-                    (Down, Down) => {
-                        self.counter = match self.counter {
-                            (_, col) => (value - 1, col),
-                        }
-                    }
-                    (Right, Right) => {
-                        self.counter = match self.counter {
-                            (row, _) => (row, value - 1),
-                        }
-                    }
-                    (Left, Left) => {
-                        self.counter = match self.counter {
-                            (row, _) => (row, value + 1),
-                        }
-                    }
-                    _ => { }
+        self.underflowed = branch && !zero;
+        branch
+    }
+
+    /// Reads a line holding a number from the input.
+    fn read_integer(&mut self) -> int {
+        let line = self.input.read_line().unwrap();
+        from_str(line.as_slice().trim()).unwrap()
+    }
+
+    fn read_character(&mut self) -> int {
+        self.input.read_char().unwrap() as int
+    }
+
+    fn print_integer(&mut self, v: int) {
+        let _ = self.out.write_int(v);
+        notify!(self, on_output(format!("{}", v).as_slice()));
+    }
+
+    fn print_character(&mut self, v: int) {
+        let c = std::char::from_u32(v as u32);
+        let _ = self.out.write_char(c.unwrap());
+        notify!(self, on_output(String::from_char(1, c.unwrap()).as_slice()));
+    }
+
+    fn pick(&mut self) -> Option<int> {
+        let index = self.storage_index;
+        let v = self.storage().pick();
+        match v {
+            Some(v) => {
+                match self.loops {
+                    Some(ref mut loops) => { loops.pick(index, v); }
+                    None => { }
                 }
-                (self.direction, self.last_move)
+                self.record(history::Picked(index, v));
+                notify!(self, on_pop(index, v));
             }
-        };
+            None => { }
+        }
+        v
+    }
 
-        if branch {
-            direction_move = match direction_move {
-                (direction, (row, col)) => (
-                    match direction {
-                        Right => Left,
-                        Left => Right,
-                        Up => Down,
-                        Down => Up,
-                    },
-                    (-row, -col)
-                )
-            }
+    fn put(&mut self, v: int) {
+        let index = self.storage_index;
+        self.storage().put(v);
+        match self.loops {
+            Some(ref mut loops) => { loops.put(index, v); }
+            None => { }
+        }
+        self.record(history::Put(index));
+        notify!(self, on_push(index, v));
+    }
+
+    fn record(&mut self, edit: history::Edit) {
+        match self.history {
+            Some(ref mut history) => { history.record(edit); }
+            None => { }
+        }
+    }
+
+    fn forget_loop(&mut self) {
+        match self.loops {
+            Some(ref mut loops) => { loops.forget(); }
+            None => { }
         }
+    }
 
-        match direction_move {
-            (direction, (row_diff, col_diff)) => {
+    fn replayed_input(&mut self) -> Option<int> {
+        match self.history {
+            Some(ref mut history) => history.replayed_input(),
+            None => None,
+        }
+    }
+
+    pub fn instruct(&mut self, instruction: &Instruction) -> bool {
+        let position = self.counter();
+        if !instruction.is_virtual() {
+            notify!(self, on_step(position, instruction));
+        }
+        match instruction.operation {
+            HaltOperation => {
+                //pringln!("halt! {:?}", syllable);
+                self.reflected = false;
+                self.underflowed = false;
+                notify!(self, on_halt(position));
+                return true;
+            }
+            operation => {
+                match self.history {
+                    Some(ref mut history) => {
+                        history.begin(history::Step {
+                            counter: self.counter,
+                            direction: self.direction,
+                            last_move: self.last_move,
+                            storage_index: self.storage_index,
+                            reflected: self.reflected,
+                            underflowed: self.underflowed,
+                            edits: Vec::new(),
+                        });
+                    }
+                    None => { }
+                }
+                let branch = self.operate(operation);
+                self.reflected = branch;
+                if branch {
+                    notify!(self, on_reflect(position, instruction));
+                }
+                let (counter, direction, last_move) = instruction.advance(self.counter, self.direction, self.last_move, branch);
+                self.counter = counter;
                 self.direction = direction;
-                self.counter = match self.counter {
-                    (row, col) => (row + row_diff, col + col_diff)
+                self.last_move = last_move;
+                let due = match self.history {
+                    Some(ref history) => history.checkpoint_due(),
+                    None => false,
                 };
-                self.last_move = (row_diff, col_diff);
+                if due {
+                    let checkpoint = self.checkpoint();
+                    self.history.get_mut_ref().push_checkpoint(checkpoint);
+                }
             }
-        };
+        }
         false
     }
 
@@ -650,13 +1110,401 @@ impl Interpreter {
         let syllable = match self.counter {
             (row, col) => self.source._get((row, col))
         };
-        self.instruct(&syllable)
+        let position = self.counter();
+        let direction = self.direction;
+        self.breakpoint_reported = false;
+        let halted = self.instruct(&syllable);
+        if !syllable.is_virtual() {
+            match self.profile {
+                Some(ref mut profile) => { profile.record(position, direction, self.underflowed); }
+                None => { }
+            }
+            match self.coverage {
+                Some(ref mut coverage) => { coverage.record(&self.source, position, self.reflected); }
+                None => { }
+            }
+        }
+        match self.tracer.take() {
+            Some(mut tracer) => {
+                tracer.record(self, position, &syllable);
+                self.tracer = Some(tracer);
+            }
+            None => { }
+        }
+        halted
+    }
+
+    /// Writes a record of every following step to `out`.
+    pub fn set_trace(&mut self, out: Box<Writer>, format: trace::TraceFormat) {
+        self.tracer = Some(trace::Tracer::new(out, format));
+    }
+
+    /// Attaches an observer, returning its index.
+    pub fn add_observer(&mut self, observer: Box<observer::InterpreterObserver>) -> uint {
+        self.observers.push(observer);
+        self.observers.len() - 1
+    }
+
+    /// Detaches an observer; the following ones move down by one index.
+    pub fn remove_observer(&mut self, idx: uint) -> Option<Box<observer::InterpreterObserver>> {
+        self.observers.remove(idx)
+    }
+
+    /// Starts counting the executions of every cell.
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(profile::Profile::new());
+    }
+
+    pub fn profile<'a>(&'a self) -> Option<&'a profile::Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Starts recording the cells and branch outcomes executed.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(coverage::Coverage::new());
+    }
+
+    pub fn coverage<'a>(&'a self) -> Option<&'a coverage::Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Stops `run` and `run_steps` with `InfiniteLoop` when the program
+    /// returns to a state it was in without reading input since.
+    pub fn enable_loop_detection(&mut self) {
+        self.loops = Some(loops::LoopDetector::new(self.storages.as_slice()));
+    }
+
+    fn reset_loop_detection(&mut self) {
+        match self.loops {
+            Some(ref mut loops) => { loops.reset(self.storages.as_slice()); }
+            None => { }
+        }
+    }
+
+    /// Counts the step just taken, returning the loop it closes if any.
+    fn check_loop(&mut self) -> Option<loops::Loop> {
+        let key = loops::Key {
+            counter: self.counter,
+            direction: self.direction,
+            last_move: self.last_move,
+            storage_index: self.storage_index,
+        };
+        let length = match self.loops {
+            Some(ref mut loops) => match loops.check(key, self.storages.as_slice()) {
+                Some(length) => length,
+                None => { return None; }
+            },
+            None => { return None; }
+        };
+
+        // Go around the loop once more to see its cells. It ends where it
+        // started, so only the output and the history have to be held back.
+        let out = std::mem::replace(&mut self.out, box std::io::util::NullWriter as Box<Writer>);
+        let observers = std::mem::replace(&mut self.observers, Vec::new());
+        let detector = self.loops.take();
+        let history = self.history.take();
+        let mut cells = Vec::new();
+        for _ in range(0, length) {
+            let position = self.counter();
+            let instruction = self.instruction();
+            if !instruction.is_virtual() && cells.len() < loops::MAX_CELLS && !cells.contains(&position) {
+                cells.push(position);
+            }
+            self.instruct(&instruction);
+        }
+        self.out = out;
+        self.observers = observers;
+        self.loops = detector;
+        self.history = history;
+        Some(loops::Loop { length: length, cells: cells })
+    }
+
+    /// Whether the last instruction reversed the movement, because its
+    /// storage lacked values or because ㅊ picked 0.
+    pub fn reflected(&self) -> bool {
+        self.reflected
+    }
+
+    /// Whether the last instruction reversed the movement because its storage
+    /// lacked values.
+    pub fn underflowed(&self) -> bool {
+        self.underflowed
     }
 
     pub fn execute(&mut self) {
         while !self.step() { }
     }
 
+    /// Starts recording the steps executed from now on, so that `step_back`
+    /// can undo them. A checkpoint is taken every `interval` steps and the
+    /// last `limit` are kept; see the `history` module.
+    pub fn enable_history(&mut self, interval: uint, limit: uint) {
+        let checkpoint = self.checkpoint();
+        self.history = Some(history::History::new(interval, limit, checkpoint));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Undoes the last step. Returns false when the history is disabled or
+    /// does not reach further back. Output is not taken back, and observers
+    /// are not told.
+    pub fn step_back(&mut self) -> bool {
+        self.breakpoint_reported = false;
+        let step = match self.history {
+            Some(ref mut history) => history.pop(),
+            None => { return false; }
+        };
+        match step {
+            Some(step) => {
+                for edit in step.edits.iter().rev() {
+                    edit.undo(&mut self.storages);
+                }
+                self.counter = step.counter;
+                self.direction = step.direction;
+                self.last_move = step.last_move;
+                self.storage_index = step.storage_index;
+                self.reflected = step.reflected;
+                self.underflowed = step.underflowed;
+                self.reset_loop_detection();
+                return true;
+            }
+            None => { }
+        }
+
+        // The last checkpoint is the current state: go to the one before and
+        // execute the program again up to the step before this one.
+        let (checkpoint, count) = match self.history.get_mut_ref().rewind() {
+            Some(rewound) => rewound,
+            None => { return false; }
+        };
+        self.restore_checkpoint(&checkpoint);
+        let out = std::mem::replace(&mut self.out, box std::io::util::NullWriter as Box<Writer>);
+        let observers = std::mem::replace(&mut self.observers, Vec::new());
+        for _ in range(0, count) {
+            let instruction = self.instruction();
+            self.instruct(&instruction);
+        }
+        self.out = out;
+        self.observers = observers;
+        self.reset_loop_detection();
+        true
+    }
+
+    /// Steps back until a breakpoint is hit, returning its index, or until
+    /// the history does not reach further back.
+    pub fn run_back(&mut self) -> Option<uint> {
+        while self.step_back() {
+            match self.hit_breakpoint() {
+                Some(idx) => {
+                    self.breakpoint_reported = true;
+                    return Some(idx);
+                }
+                None => { }
+            }
+        }
+        None
+    }
+
+    /// Copy of the state `restore` needs to resume execution from here.
+    pub fn snapshot(&self) -> snapshot::Snapshot {
+        snapshot::Snapshot {
+            source_hash: self.source.hash(),
+            counter: self.counter(),
+            direction: self.direction,
+            last_move: self.last_move,
+            storage_index: self.storage_index,
+            reflected: self.reflected,
+            underflowed: self.underflowed,
+            storages: self.storages.iter().map(|storage| storage.values()).collect(),
+        }
+    }
+
+    /// Resumes from a snapshot taken from the same program. The history, if
+    /// enabled, starts again from the restored state.
+    pub fn restore(&mut self, snapshot: &snapshot::Snapshot) -> Result<(), String> {
+        if snapshot.source_hash != self.source.hash() {
+            return Err(String::from_str("the snapshot was taken from another program"));
+        }
+        if snapshot.storages.len() != self.storages.len() || snapshot.storage_index >= self.storages.len() {
+            return Err(String::from_str("the snapshot has invalid storages"));
+        }
+        if !self.source.contains(snapshot.counter) {
+            return Err(String::from_str("the snapshot has its counter outside the program"));
+        }
+        let (row, col) = snapshot.counter;
+        let checkpoint = history::Checkpoint {
+            counter: (row + 2, col + 2),
+            direction: snapshot.direction,
+            last_move: snapshot.last_move,
+            storage_index: snapshot.storage_index,
+            reflected: snapshot.reflected,
+            underflowed: snapshot.underflowed,
+            storages: snapshot.storages.clone(),
+        };
+        self.restore_checkpoint(&checkpoint);
+        self.breakpoint_reported = false;
+        match self.history {
+            Some(ref mut history) => { history.reset(checkpoint); }
+            None => { }
+        }
+        self.reset_loop_detection();
+        Ok(())
+    }
+
+    fn checkpoint(&self) -> history::Checkpoint {
+        history::Checkpoint {
+            counter: self.counter,
+            direction: self.direction,
+            last_move: self.last_move,
+            storage_index: self.storage_index,
+            reflected: self.reflected,
+            underflowed: self.underflowed,
+            storages: self.storages.iter().map(|storage| storage.values()).collect(),
+        }
+    }
+
+    fn restore_checkpoint(&mut self, checkpoint: &history::Checkpoint) {
+        self.counter = checkpoint.counter;
+        self.direction = checkpoint.direction;
+        self.last_move = checkpoint.last_move;
+        self.storage_index = checkpoint.storage_index;
+        self.reflected = checkpoint.reflected;
+        self.underflowed = checkpoint.underflowed;
+        for (index, values) in checkpoint.storages.iter().enumerate() {
+            let mut storage = new_storage(index);
+            for v in values.iter() {
+                storage.put(*v);
+            }
+            *self.storages.get_mut(index) = storage;
+        }
+    }
+
+    /// Steps until the program halts or a breakpoint or watchpoint stops it.
+    /// Breakpoints are checked before the cell the counter is on executes,
+    /// the first one included, and watchpoints after each step. Calling it
+    /// again resumes execution.
+    pub fn run(&mut self) -> RunResult {
+        loop {
+            match self.run_steps(std::uint::MAX) {
+                Some(result) => { return result; }
+                None => { }
+            }
+        }
+    }
+
+    /// Like `run`, but returns None after `limit` steps which neither halted
+    /// nor stopped, so that the caller can do something else in between.
+    pub fn run_steps(&mut self, limit: uint) -> Option<RunResult> {
+        for _ in range(0, limit) {
+            if !self.breakpoint_reported {
+                match self.hit_breakpoint() {
+                    Some(idx) => {
+                        self.breakpoint_reported = true;
+                        return Some(Stopped(BreakpointHit(idx)));
+                    }
+                    None => { }
+                }
+            }
+            let storage_index = self.storage_index;
+            if self.step() {
+                return Some(Halted);
+            }
+            match self.check_loop() {
+                Some(found) => { return Some(Stopped(InfiniteLoop(found))); }
+                None => { }
+            }
+            let mut hit = None;
+            for idx in range(0, self.watchpoints.len()) {
+                let now = self.watch(self.watchpoints.get(idx), storage_index);
+                let before = *self.watched.get(idx);
+                *self.watched.get_mut(idx) = now;
+                let fire = match *self.watchpoints.get(idx) {
+                    StorageSelectionWatchpoint => now,
+                    _ => now && !before,
+                };
+                if fire && hit.is_none() {
+                    hit = Some(idx);
+                }
+            }
+            match hit {
+                Some(idx) => { return Some(Stopped(WatchpointHit(idx))); }
+                None => { }
+            }
+        }
+        None
+    }
+
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> uint {
+        self.breakpoints.push(breakpoint);
+        self.breakpoints.len() - 1
+    }
+
+    /// Removes a breakpoint; the following ones move down by one index.
+    pub fn remove_breakpoint(&mut self, idx: uint) -> Option<Breakpoint> {
+        self.breakpoints.remove(idx)
+    }
+
+    pub fn breakpoints<'a>(&'a self) -> &'a [Breakpoint] {
+        self.breakpoints.as_slice()
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> uint {
+        let now = self.watch(&watchpoint, self.storage_index);
+        self.watchpoints.push(watchpoint);
+        self.watched.push(now);
+        self.watchpoints.len() - 1
+    }
+
+    /// Removes a watchpoint; the following ones move down by one index.
+    pub fn remove_watchpoint(&mut self, idx: uint) -> Option<Watchpoint> {
+        let _ = self.watched.remove(idx);
+        self.watchpoints.remove(idx)
+    }
+
+    pub fn watchpoints<'a>(&'a self) -> &'a [Watchpoint] {
+        self.watchpoints.as_slice()
+    }
+
+    fn watch(&self, watchpoint: &Watchpoint, previous_index: uint) -> bool {
+        match *watchpoint {
+            LengthWatchpoint(index, predicate) => predicate(self.storage_at(index).len()),
+            TopWatchpoint(index, predicate) => predicate(self.storage_at(index).peek()),
+            StorageSelectionWatchpoint => self.storage_index != previous_index,
+        }
+    }
+
+    fn hit_breakpoint(&self) -> Option<uint> {
+        let counter = self.counter();
+        let instruction = self.instruction();
+        for (idx, breakpoint) in self.breakpoints.iter().enumerate() {
+            let hit = match *breakpoint {
+                CellBreakpoint(pos) => pos == counter,
+                DirectedCellBreakpoint(pos, direction) => pos == counter && direction == self.direction,
+                SyllableBreakpoint(c) => instruction.char() == Some(c),
+                OperationBreakpoint(kind) => !instruction.is_virtual() && instruction.operation.kind() == kind,
+            };
+            if hit {
+                return Some(idx);
+            }
+        }
+        None
+    }
+
+}
+
+/// Empty storage for an index: ㅇ is the queue. ㅎ is reserved for an
+/// extension protocol and behaves as a queue until one is defined.
+fn new_storage(index: uint) -> Box<Storage> {
+    match index {
+        21 | 27 => {
+            box Queue::new() as Box<Storage>
+        }
+        _ => {
+            box Stack::new() as Box<Storage>
+        }
+    }
 }
 
 #[test]
@@ -0,0 +1,332 @@
+//! Warnings about programs which are valid but probably not what was meant.
+
+use std::char;
+use std::collections::HashSet;
+use std::io::IoResult;
+
+use super::{Source, Instruction, InterpreterDirection, Up, Down, Left, Right};
+use super::{AllowHorizontalMovement, AllowVerticalMovement, DisallowMovement};
+use super::{InstructionOperation, NoOperation, PushConstantOperation, PushDuplicationOperation};
+use super::{PushIntegerInputOperation, PushCharInputOperation, BinaryOperation, PopOperation};
+use super::{PrintIntegerOperation, PrintCharOperation, SwapOperation, CompareOperation, BranchOperation};
+use super::{ChangeStorageOperation, MoveToStorageOperation, HaltOperation};
+use graph::{Graph, Goto};
+use reachability::Reachability;
+use depth::{DepthAnalysis, Never, Sometimes, Always};
+use usage;
+
+/// Instructions looked back through to find the value on top of a storage.
+static LOOK_BEHIND: uint = 32;
+
+pub struct Warning {
+    /// Cell the warning is about, in program coordinates.
+    pub position: Option<(int, int)>,
+    pub message: String,
+}
+
+impl Warning {
+    fn at(position: (int, int), message: String) -> Warning {
+        Warning { position: Some(position), message: message }
+    }
+
+    /// Writes `path:line:column: warning: message`, with lines and columns
+    /// starting at 1 as editors expect.
+    pub fn write(&self, path: &str, out: &mut Writer) -> IoResult<()> {
+        match self.position {
+            Some((row, col)) => writeln!(out, "{}:{}:{}: warning: {}", path, row + 1, col + 1, self.message),
+            None => writeln!(out, "{}: warning: {}", path, self.message),
+        }
+    }
+}
+
+/// Every warning for `source`, in the order of the checks and then of the
+/// cells. Syllables which underflow only on some paths are reported if
+/// `may_underflow` is set.
+pub fn lint(source: &Source, may_underflow: bool) -> Vec<Warning> {
+    let graph = Graph::from_source(source);
+    let depth = DepthAnalysis::from_graph(&graph);
+    let mut warnings = Vec::new();
+    check_reachability(source, &graph, &mut warnings);
+    check_underflow(source, &depth, may_underflow, &mut warnings);
+    check_known_values(&graph, &mut warnings);
+    check_extension_storage(source, &mut warnings);
+    check_entry_reflections(&graph, &depth, &mut warnings);
+    check_characters(source, &mut warnings);
+    warnings
+}
+
+/// Syllables which can never be executed, a run of them in a row at a time,
+/// and programs which can never halt.
+fn check_reachability(source: &Source, graph: &Graph, warnings: &mut Vec<Warning>) {
+    let reachability = Reachability::from_graph(graph);
+    if !reachability.halts {
+        warnings.push(Warning {
+            position: None,
+            message: String::from_str("no halt (ㅎ) can be reached, so the program never ends"),
+        });
+    }
+
+    let cells = reachability.unreachable(source);
+    let mut i = 0;
+    while i < cells.len() {
+        let (row, col) = *cells.get(i);
+        let mut len = 1;
+        while i + len < cells.len() && *cells.get(i + len) == (row, col + len as int) {
+            len += 1;
+        }
+        let message = if len == 1 {
+            format!("{} can never be executed", source.get((row, col)).char().unwrap())
+        } else {
+            format!("{} cells from here can never be executed", len)
+        };
+        warnings.push(Warning::at((row, col), message));
+        i += len;
+    }
+}
+
+/// Syllables which reflect whenever they are executed because their storage
+/// never holds enough values there. Those which only underflow on some paths
+/// are usually meant to, and are only reported with `sometimes`.
+fn check_underflow(source: &Source, analysis: &DepthAnalysis, sometimes: bool, warnings: &mut Vec<Warning>) {
+    for row in range(0, source.height()) {
+        for col in range(0, source.row_len(row)) {
+            let position = (row as int, col as int);
+            match analysis.underflow(position) {
+                Some(Always) => {
+                    let instruction = source.get(position);
+                    warnings.push(Warning::at(position, format!("{} ({}) always underflows its storage and reflects",
+                                                                instruction.char().unwrap(), instruction.mnemonic())));
+                }
+                Some(Sometimes) if sometimes => {
+                    let instruction = source.get(position);
+                    warnings.push(Warning::at(position, format!("{} ({}) may underflow its storage and reflect",
+                                                                instruction.char().unwrap(), instruction.mnemonic())));
+                }
+                _ => { }
+            }
+        }
+    }
+}
+
+/// Divisions by zero and characters printed from values which are not
+/// characters, where the instructions just before leave a known value on
+/// top of a stack.
+fn check_known_values(graph: &Graph, warnings: &mut Vec<Warning>) {
+    let selections = usage::selections(graph);
+    let mut predecessors: Vec<Vec<(uint, bool)>> = Vec::from_elem(graph.len(), Vec::new());
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        match node.next {
+            Goto(next) => { predecessors.get_mut(next).push((idx, false)); }
+            _ => { }
+        }
+        match node.reflected {
+            Some(Goto(next)) => { predecessors.get_mut(next).push((idx, true)); }
+            _ => { }
+        }
+    }
+
+    let mut found = Vec::new();
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        let operation = node.instruction.operation();
+        let divides = match node.instruction.binary_operator() {
+            Some("div") | Some("mod") => true,
+            _ => false,
+        };
+        let prints = match operation {
+            PrintCharOperation => true,
+            _ => false,
+        };
+        if !(divides || prints) || selections.get(idx).iter().any(|index| *index == 21 || *index == 27) {
+            continue;
+        }
+        let top = match known_top(graph, predecessors.as_slice(), idx) {
+            Some(top) => top,
+            None => { continue; }
+        };
+        let syllable = node.instruction.char().unwrap();
+        let position = node.state.position();
+        if divides && top == 0 {
+            found.push((position, format!("{} ({}) divides by 0 and fails", syllable, node.instruction.mnemonic())));
+        } else if prints && (top < 0 || char::from_u32(top as u32).is_none()) {
+            found.push((position, format!("{} prints {}, which is not a character, and fails", syllable, top)));
+        }
+    }
+    found.sort();
+    found.dedup();
+    for (position, message) in found.move_iter() {
+        warnings.push(Warning::at(position, message));
+    }
+}
+
+/// Value on top of the selected storage when node `idx` is executed, if the
+/// only way there is a run of instructions which leaves a known value.
+fn known_top(graph: &Graph, predecessors: &[Vec<(uint, bool)>], idx: uint) -> Option<int> {
+    let mut chain = Vec::new();
+    let mut current = idx;
+    while chain.len() < LOOK_BEHIND {
+        let (previous, reflected) = match predecessors[current].as_slice() {
+            [only] => only,
+            _ => { break; }
+        };
+        if reflected || previous == idx || chain.contains(&previous) {
+            break;
+        }
+        match graph.nodes.get(previous).instruction.operation() {
+            ChangeStorageOperation(_) | MoveToStorageOperation(_) => { break; }
+            _ => { }
+        }
+        chain.push(previous);
+        current = previous;
+    }
+
+    // Values below the ones the chain pushes are unknown.
+    let mut stack: Vec<Option<int>> = Vec::new();
+    for previous in chain.iter().rev() {
+        let instruction = graph.nodes.get(*previous).instruction;
+        simulate(&instruction, instruction.operation(), &mut stack);
+    }
+    stack.pop().unwrap_or(None)
+}
+
+fn pick(stack: &mut Vec<Option<int>>) -> Option<int> {
+    stack.pop().unwrap_or(None)
+}
+
+fn simulate(instruction: &Instruction, operation: InstructionOperation, stack: &mut Vec<Option<int>>) {
+    match operation {
+        PushConstantOperation(v) => { stack.push(Some(v)); }
+        PushIntegerInputOperation | PushCharInputOperation => { stack.push(None); }
+        PushDuplicationOperation => {
+            let top = stack.last().map(|v| *v).unwrap_or(None);
+            stack.push(top);
+        }
+        BinaryOperation(op) => {
+            let v1 = pick(stack);
+            let v2 = pick(stack);
+            let divides = match instruction.binary_operator() {
+                Some("div") | Some("mod") => true,
+                _ => false,
+            };
+            stack.push(match (v1, v2) {
+                (Some(0), _) if divides => None,
+                (Some(v1), Some(v2)) => Some(op(v1, v2)),
+                _ => None,
+            });
+        }
+        CompareOperation => {
+            let v1 = pick(stack);
+            let v2 = pick(stack);
+            stack.push(match (v1, v2) {
+                (Some(v1), Some(v2)) => Some(if v2 >= v1 { 1 } else { 0 }),
+                _ => None,
+            });
+        }
+        SwapOperation => {
+            let v1 = pick(stack);
+            let v2 = pick(stack);
+            stack.push(v1);
+            stack.push(v2);
+        }
+        PopOperation | PrintIntegerOperation | PrintCharOperation | BranchOperation => { pick(stack); }
+        NoOperation | HaltOperation | ChangeStorageOperation(_) | MoveToStorageOperation(_) => { }
+    }
+}
+
+/// Selections of and moves to the ㅎ storage, which implementations differ
+/// on and extensions claim.
+fn check_extension_storage(source: &Source, warnings: &mut Vec<Warning>) {
+    for row in range(0, source.height()) {
+        for col in range(0, source.row_len(row)) {
+            let position = (row as int, col as int);
+            let instruction = source.get(position);
+            match instruction.operation() {
+                ChangeStorageOperation(27) | MoveToStorageOperation(27) => {
+                    warnings.push(Warning::at(position, format!("{} uses the ㅎ storage, which is reserved for extensions",
+                                                                instruction.char().unwrap())));
+                }
+                _ => { }
+            }
+        }
+    }
+}
+
+/// ㅡ, ㅣ and ㅢ turning the counter back on the way every execution starts
+/// with, before any branch or possible underflow, which is rarely meant.
+fn check_entry_reflections(graph: &Graph, depth: &DepthAnalysis, warnings: &mut Vec<Warning>) {
+    let mut visited = HashSet::new();
+    let mut idx = 0;
+    while idx < graph.len() && !visited.contains(&idx) {
+        visited.insert(idx);
+        let node = graph.nodes.get(idx);
+        let operation = node.instruction.operation();
+        let deterministic = match operation {
+            HaltOperation | BranchOperation => false,
+            _ if operation.can_reflect() => depth.underflow(node.state.position()) == Some(Never),
+            _ => true,
+        };
+        if !deterministic {
+            break;
+        }
+        let reflecting = match node.instruction.move {
+            AllowHorizontalMovement | AllowVerticalMovement | DisallowMovement => true,
+            _ => false,
+        };
+        let state = node.state;
+        let (_, direction, _) = node.instruction.advance(state.counter, state.direction, state.last_move, false);
+        if reflecting && direction == opposite(state.direction) {
+            warnings.push(Warning::at(state.position(), format!("{} turns the counter back on the way from the start",
+                                                                node.instruction.char().unwrap())));
+        }
+        idx = match node.next {
+            Goto(next) => next,
+            _ => { break; }
+        };
+    }
+}
+
+fn opposite(direction: InterpreterDirection) -> InterpreterDirection {
+    match direction {
+        Up => Down,
+        Down => Up,
+        Left => Right,
+        Right => Left,
+    }
+}
+
+/// Characters which look like code or like nothing: compatibility jamo do
+/// nothing, and invisible spaces take a column, shifting the cells after
+/// them.
+fn check_characters(source: &Source, warnings: &mut Vec<Warning>) {
+    for row in range(0, source.height()) {
+        for col in range(0, source.row_len(row)) {
+            let position = (row as int, col as int);
+            let instruction = source.get(position);
+            if instruction.is_hangeul() {
+                continue;
+            }
+            let message = match instruction.char() {
+                Some(c) if c >= '\u3131' && c <= '\u318e' => {
+                    format!("{} is a compatibility jamo, not a syllable, and does nothing", c)
+                }
+                Some(c) => match invisible_name(c) {
+                    Some(name) => format!("U+{:04X} ({}) takes a column, shifting the cells after it", c as u32, name),
+                    None => { continue; }
+                },
+                None => { continue; }
+            };
+            warnings.push(Warning::at(position, message));
+        }
+    }
+}
+
+fn invisible_name(c: char) -> Option<&'static str> {
+    match c {
+        '\u00a0' => Some("no-break space"),
+        '\u200b' => Some("zero width space"),
+        '\u200c' => Some("zero width non-joiner"),
+        '\u200d' => Some("zero width joiner"),
+        '\u2060' => Some("word joiner"),
+        '\ufeff' => Some("zero width no-break space"),
+        _ => None,
+    }
+}
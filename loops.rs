@@ -0,0 +1,183 @@
+//! Detection of executions which repeat a state forever.
+//!
+//! The next step depends only on the counter, the direction, the last move,
+//! the selected storage and the storage contents, unless it reads input. When
+//! all of them recur without input read in between, the program is stuck in
+//! a loop. The contents are summarized by a rolling hash of each storage,
+//! updated as values are put and picked, and the state is compared against
+//! one saved at steps 1, 2, 4, 8 and so on since the last input, as in
+//! Brent's cycle detection. A matching hash is confirmed against the saved
+//! values before the loop is reported.
+
+use super::{InterpreterDirection, Storage};
+
+/// Multiplier of the rolling hashes; odd, so that it can be divided by.
+static BASE: u64 = 0x100000001b3;
+
+/// Loop found at run time.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Loop {
+    /// Steps one turn of the loop takes.
+    pub length: u64,
+    /// Cells executed in a turn, in program coordinates and in the order
+    /// they are first executed. At most `MAX_CELLS` are listed.
+    pub cells: Vec<(int, int)>,
+}
+
+pub static MAX_CELLS: uint = 1000;
+
+impl Loop {
+    /// `every 3 steps through 0,0 0,1 0,2`, for diagnostics.
+    pub fn describe(&self) -> String {
+        let cells: Vec<String> = self.cells.iter().map(|&(row, col)| format!("{},{}", row, col)).collect();
+        format!("every {} steps through {}{}", self.length, cells.connect(" "),
+                if self.cells.len() >= MAX_CELLS { " ..." } else { "" })
+    }
+}
+
+/// Hash of the values of a storage where the value at offset `i` from the
+/// front counts `mix(v) * BASE^i`, so that values can come and go at either
+/// end. `hash` counts offsets from where the front was when the storage was
+/// empty; `head_inverse` brings it back to the current front.
+struct RollingHash {
+    queue: bool,
+    hash: u64,
+    head_power: u64,
+    head_inverse: u64,
+    len_power: u64,
+}
+
+impl RollingHash {
+    fn new(queue: bool, values: &[int]) -> RollingHash {
+        let mut hash = RollingHash { queue: queue, hash: 0, head_power: 1, head_inverse: 1, len_power: 1 };
+        for v in values.iter() {
+            hash.put(*v);
+        }
+        hash
+    }
+
+    fn put(&mut self, v: int) {
+        self.hash += mix(v) * self.head_power * self.len_power;
+        self.len_power *= BASE;
+    }
+
+    fn pick(&mut self, v: int) {
+        self.len_power *= inverse(BASE);
+        if self.queue {
+            self.hash -= mix(v) * self.head_power;
+            self.head_power *= BASE;
+            self.head_inverse *= inverse(BASE);
+        } else {
+            self.hash -= mix(v) * self.head_power * self.len_power;
+        }
+    }
+
+    fn value(&self) -> u64 {
+        self.hash * self.head_inverse
+    }
+}
+
+fn mix(v: int) -> u64 {
+    let mut x = v as u64 + 0x9e3779b97f4a7c15;
+    x = (x ^ (x >> 30)) * 0xbf58476d1ce4e5b9;
+    x = (x ^ (x >> 27)) * 0x94d049bb133111eb;
+    x ^ (x >> 31)
+}
+
+/// Multiplicative inverse of an odd number modulo 2^64, by Newton's method.
+fn inverse(n: u64) -> u64 {
+    let mut x = n;
+    for _ in range(0u, 6) {
+        x *= 2 - n * x;
+    }
+    x
+}
+
+/// Everything the following steps depend on, but the storage contents.
+#[deriving(Clone, PartialEq)]
+pub struct Key {
+    pub counter: (int, int),
+    pub direction: InterpreterDirection,
+    pub last_move: (int, int),
+    pub storage_index: uint,
+}
+
+struct Saved {
+    key: Key,
+    hash: u64,
+    storages: Vec<Vec<int>>,
+    step: u64,
+}
+
+pub struct LoopDetector {
+    hashes: Vec<RollingHash>,
+    saved: Option<Saved>,
+    steps: u64,
+    /// Step at which the state is saved next, and the interval after it.
+    next_save: u64,
+    interval: u64,
+}
+
+impl LoopDetector {
+    pub fn new(storages: &[Box<Storage>]) -> LoopDetector {
+        let mut detector = LoopDetector { hashes: Vec::new(), saved: None, steps: 0, next_save: 1, interval: 1 };
+        detector.reset(storages);
+        detector
+    }
+
+    /// Starts over from the current contents, after they changed in ways
+    /// the detector was not told about.
+    pub fn reset(&mut self, storages: &[Box<Storage>]) {
+        self.hashes = storages.iter().enumerate().map(|(index, storage)| {
+            RollingHash::new(index == 21 || index == 27, storage.values().as_slice())
+        }).collect();
+        self.forget();
+    }
+
+    /// Drops the saved state, as a step which read input cannot be repeated.
+    pub fn forget(&mut self) {
+        self.saved = None;
+        self.next_save = self.steps + 1;
+        self.interval = 1;
+    }
+
+    pub fn put(&mut self, index: uint, v: int) {
+        self.hashes.get_mut(index).put(v);
+    }
+
+    pub fn pick(&mut self, index: uint, v: int) {
+        self.hashes.get_mut(index).pick(v);
+    }
+
+    /// Hashes a storage again after its values were rearranged.
+    pub fn reload(&mut self, index: uint, storage: &Storage) {
+        *self.hashes.get_mut(index) = RollingHash::new(index == 21 || index == 27, storage.values().as_slice());
+    }
+
+    /// Counts a step which ended in the given state, returning the length of
+    /// the loop if the state was seen before.
+    pub fn check(&mut self, key: Key, storages: &[Box<Storage>]) -> Option<u64> {
+        self.steps += 1;
+        let hash = self.hashes.iter().fold(0u64, |hash, storage| hash * BASE + storage.value());
+        match self.saved {
+            Some(ref saved) if saved.key == key && saved.hash == hash => {
+                let same = saved.storages.iter().zip(storages.iter()).all(|(values, storage)| *values == storage.values());
+                if same {
+                    return Some(self.steps - saved.step);
+                }
+            }
+            _ => { }
+        }
+        if self.steps == self.next_save {
+            self.saved = Some(Saved {
+                key: key,
+                hash: hash,
+                storages: storages.iter().map(|storage| storage.values()).collect(),
+                step: self.steps,
+            });
+            self.interval *= 2;
+            self.next_save = self.steps + self.interval;
+        }
+        None
+    }
+}
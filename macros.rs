@@ -0,0 +1,76 @@
+#![crate_id="aheui_macros"]
+#![crate_type="dylib"]
+#![license="BSD simplified"]
+#![feature(macro_registrar)]
+
+//! Syntax extension embedding Aheui programs at compile time.
+//!
+//! ```ignore
+//! #![feature(phase)]
+//! #[phase(syntax)]
+//! extern crate aheui_macros;
+//!
+//! aheui!(sum, "방방다망희")
+//! aheui!(hello, file "hello.ah")
+//! ```
+//!
+//! Each invocation expands to the function `aheui::transpile` generates:
+//! `fn name<R: Buffer, W: Writer>(input: &mut R, output: &mut W)`. Paths
+//! given with `file` are relative to the invoking source file.
+
+extern crate syntax;
+extern crate aheui;
+
+use syntax::ast;
+use syntax::codemap::Span;
+use syntax::ext::base::{SyntaxExtension, NormalTT, BasicMacroExpander, ExtCtxt, MacResult, MacItem, DummyResult};
+use syntax::parse;
+use syntax::parse::token;
+
+#[macro_registrar]
+pub fn macro_registrar(register: |ast::Name, SyntaxExtension|) {
+    register(token::intern("aheui"),
+             NormalTT(box BasicMacroExpander { expander: expand_aheui, span: None }, None));
+}
+
+fn expand_aheui(cx: &mut ExtCtxt, sp: Span, tts: &[ast::TokenTree]) -> Box<MacResult> {
+    let mut parser = cx.new_parser_from_tts(tts);
+    let name = parser.parse_ident();
+    parser.expect(&token::COMMA);
+    let from_file = match parser.token {
+        token::IDENT(ident, _) if token::get_ident(ident).get() == "file" => {
+            parser.bump();
+            true
+        }
+        _ => false,
+    };
+    let (literal, _) = parser.parse_str();
+    if parser.token != token::EOF {
+        cx.span_err(parser.span, "aheui! takes a name and a program or `file` path");
+        return DummyResult::any(sp);
+    }
+
+    let program = if from_file {
+        let invoker = Path::new(cx.codemap().span_to_filename(sp));
+        let path = invoker.dir_path().join(literal.get());
+        match std::io::File::open(&path).read_to_str() {
+            Ok(text) => text,
+            Err(e) => {
+                cx.span_err(sp, format!("couldn't read {}: {}", path.display(), e).as_slice());
+                return DummyResult::any(sp);
+            }
+        }
+    } else {
+        String::from_str(literal.get())
+    };
+
+    let source = aheui::Source::from_str(program.as_slice());
+    let code = aheui::transpile::transpile(&source, token::get_ident(name).get());
+    match parse::parse_item_from_source_str(String::from_str("<aheui! macro>"), code, cx.cfg(), cx.parse_sess()) {
+        Some(item) => MacItem::new(item),
+        None => {
+            cx.span_err(sp, "aheui! generated an invalid item");
+            DummyResult::any(sp)
+        }
+    }
+}
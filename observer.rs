@@ -0,0 +1,26 @@
+//! Callbacks for tools watching an `Interpreter` run.
+//!
+//! Every method does nothing by default, so an observer implements only the
+//! events it needs. Without observers an event costs the interpreter a length
+//! check. Positions are in program coordinates and storages are indices as in
+//! `storage_names`.
+
+use super::Instruction;
+
+pub trait InterpreterObserver {
+    /// An instruction is about to be executed. Steps through the walls around
+    /// the program are not reported.
+    fn on_step(&mut self, _position: (int, int), _instruction: &Instruction) { }
+    fn on_push(&mut self, _storage: uint, _value: int) { }
+    fn on_pop(&mut self, _storage: uint, _value: int) { }
+    /// The two values next to be picked from a storage were exchanged.
+    fn on_swap(&mut self, _storage: uint) { }
+    /// Text the program wrote.
+    fn on_output(&mut self, _text: &str) { }
+    /// A value the program read, before it is pushed.
+    fn on_input(&mut self, _value: int) { }
+    /// The instruction at `position` reversed the movement, because its
+    /// storage lacked values or because a branch popped 0.
+    fn on_reflect(&mut self, _position: (int, int), _instruction: &Instruction) { }
+    fn on_halt(&mut self, _position: (int, int)) { }
+}
@@ -0,0 +1,136 @@
+//! Execution counts per cell, to find where a program spends its time.
+//!
+//! Only syllables of the program are counted, not the steps through the
+//! walls around it. The direction of an execution is the one the counter
+//! arrived with.
+
+use std::collections::HashMap;
+use std::io::IoResult;
+
+use super::{Source, InterpreterDirection, Down, Up, Right, Left};
+use visualize::width;
+
+static DIRECTIONS: [InterpreterDirection, ..4] = [Down, Up, Right, Left];
+/// 256-color backgrounds of the heat map, from the coldest to the hottest.
+static HEAT_COLORS: [uint, ..6] = [17, 27, 36, 184, 208, 196];
+
+pub struct Profile {
+    steps: u64,
+    cells: HashMap<(int, int), u64>,
+    directed: HashMap<((int, int), InterpreterDirection), u64>,
+    underflows: HashMap<(int, int), u64>,
+}
+
+impl Profile {
+    pub fn new() -> Profile {
+        Profile {
+            steps: 0,
+            cells: HashMap::new(),
+            directed: HashMap::new(),
+            underflows: HashMap::new(),
+        }
+    }
+
+    /// Counts an execution of the cell at `position`, in program coordinates.
+    pub fn record(&mut self, position: (int, int), direction: InterpreterDirection, underflowed: bool) {
+        self.steps += 1;
+        self.cells.insert_or_update_with(position, 1, |_, count| *count += 1);
+        self.directed.insert_or_update_with((position, direction), 1, |_, count| *count += 1);
+        if underflowed {
+            self.underflows.insert_or_update_with(position, 1, |_, count| *count += 1);
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn count(&self, position: (int, int)) -> u64 {
+        *self.cells.find(&position).unwrap_or(&0)
+    }
+
+    pub fn count_directed(&self, position: (int, int), direction: InterpreterDirection) -> u64 {
+        *self.directed.find(&(position, direction)).unwrap_or(&0)
+    }
+
+    /// Times the instruction at `position` found too few values in the storage.
+    /// A ㅊ which branches on zero is not counted.
+    pub fn underflows(&self, position: (int, int)) -> u64 {
+        *self.underflows.find(&position).unwrap_or(&0)
+    }
+
+    /// Executed cells, the most executed first.
+    pub fn hot_cells(&self) -> Vec<((int, int), u64)> {
+        let mut cells: Vec<((int, int), u64)> = self.cells.iter().map(|(position, count)| (*position, *count)).collect();
+        cells.sort_by(|a, b| {
+            let (position_a, count_a) = *a;
+            let (position_b, count_b) = *b;
+            if count_a != count_b { count_b.cmp(&count_a) } else { position_a.cmp(&position_b) }
+        });
+        cells
+    }
+
+    /// Writes the `top` hot cells with their counts per direction.
+    pub fn write_report(&self, source: &Source, out: &mut Writer, top: uint) -> IoResult<()> {
+        try!(writeln!(out, "{} steps", self.steps));
+        try!(writeln!(out, "  row   col     count      %  down    up right  left underflows"));
+        for &(position, count) in self.hot_cells().iter().take(top) {
+            let (row, col) = position;
+            let syllable = source.get(position).char().unwrap_or(' ');
+            let percent = 100.0 * count as f64 / self.steps as f64;
+            try!(write!(out, "{:5} {:5} {} {:9} {:6.2}", row, col, syllable, count, percent));
+            for direction in DIRECTIONS.iter() {
+                try!(write!(out, " {:5}", self.count_directed(position, *direction)));
+            }
+            try!(writeln!(out, " {:10}", self.underflows(position)));
+        }
+        Ok(())
+    }
+
+    /// Writes the program with each executed cell colored by its count.
+    pub fn write_heat_map(&self, source: &Source, out: &mut Writer) -> IoResult<()> {
+        let max = self.cells.values().map(|count| *count).max().unwrap_or(0);
+        for row in range(0, source.height()) {
+            for col in range(0, source.row_len(row)) {
+                let position = (row as int, col as int);
+                let c = match source.get(position).char() {
+                    Some(c) if !c.is_control() => c,
+                    _ => ' ',
+                };
+                let padding = if width(c) == 1 { " " } else { "" };
+                let count = self.count(position);
+                if count == 0 {
+                    try!(write!(out, "{}{}", c, padding));
+                } else {
+                    let level = (count - 1) * HEAT_COLORS.len() as u64 / max;
+                    try!(write!(out, "\x1b[48;5;{}m{}{}\x1b[0m", HEAT_COLORS[level as uint], c, padding));
+                }
+            }
+            try!(writeln!(out, ""));
+        }
+        Ok(())
+    }
+
+    /// Writes one line per executed cell:
+    /// `row,col,syllable,count,down,up,right,left,underflows`.
+    pub fn write_csv(&self, source: &Source, out: &mut Writer) -> IoResult<()> {
+        try!(writeln!(out, "row,col,syllable,count,down,up,right,left,underflows"));
+        let mut positions: Vec<(int, int)> = self.cells.keys().map(|position| *position).collect();
+        positions.sort();
+        for position in positions.iter() {
+            let (row, col) = *position;
+            let syllable = match source.get(*position).char() {
+                Some('"') => String::from_str("\"\"\"\""),
+                Some(',') => String::from_str("\",\""),
+                Some(c) if !c.is_control() => String::from_char(1, c),
+                _ => String::new(),
+            };
+            try!(write!(out, "{},{},{},{}", row, col, syllable, self.count(*position)));
+            for direction in DIRECTIONS.iter() {
+                try!(write!(out, ",{}", self.count_directed(*position, *direction)));
+            }
+            try!(writeln!(out, ",{}", self.underflows(*position)));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+//! Cells of a program that some execution can reach.
+//!
+//! Every state of the `Graph` is assumed reachable: both outcomes of a branch
+//! and the reflection of every operation that can underflow are followed,
+//! whatever the storages would hold. A cell outside the result can therefore
+//! never be executed, while a cell inside it may still be dead in practice.
+
+use std::collections::HashSet;
+
+use super::{Source, HaltOperation};
+use graph::Graph;
+
+pub struct Reachability {
+    /// Positions of the syllables which may be executed.
+    pub cells: HashSet<(int, int)>,
+    /// Whether some `HaltOperation` may be executed.
+    pub halts: bool,
+}
+
+impl Reachability {
+    pub fn from_source(source: &Source) -> Reachability {
+        Reachability::from_graph(&Graph::from_source(source))
+    }
+
+    pub fn from_graph(graph: &Graph) -> Reachability {
+        let mut reachability = Reachability { cells: HashSet::new(), halts: false };
+        for node in graph.nodes.iter() {
+            if !node.instruction.is_hangeul() {
+                continue;
+            }
+            reachability.cells.insert(node.state.position());
+            match node.instruction.operation() {
+                HaltOperation => { reachability.halts = true; }
+                _ => { }
+            }
+        }
+        reachability
+    }
+
+    pub fn reachable(&self, position: (int, int)) -> bool {
+        self.cells.contains(&position)
+    }
+
+    /// Syllables of `source` which can never be executed, row by row.
+    pub fn unreachable(&self, source: &Source) -> Vec<(int, int)> {
+        let mut cells = Vec::new();
+        for row in range(0, source.height()) {
+            for col in range(0, source.row_len(row)) {
+                let position = (row as int, col as int);
+                if source.get(position).is_hangeul() && !self.reachable(position) {
+                    cells.push(position);
+                }
+            }
+        }
+        cells
+    }
+}
@@ -0,0 +1,167 @@
+//! Saved interpreter state, to resume a program later or in another process.
+//!
+//! The text format is line-oriented and stable:
+//!
+//! ```text
+//! aheui-snapshot 1
+//! source 9c4f3b1e0d7a2285
+//! counter 0 3
+//! direction right
+//! last-move 0 1
+//! storage 0
+//! reflected false
+//! underflowed false
+//! values 0 5
+//! values 21 1 2 3
+//! ```
+//!
+//! `source` is `Source::hash` of the program, `counter` is in program
+//! coordinates and `values` lists a non-empty storage by index, from the
+//! bottom of a stack or the front of a queue. Storages without a `values`
+//! line are empty, and a missing `underflowed` line reads as false.
+//! Positions in the input and output streams are not saved.
+
+use std::io::IoResult;
+use std::num::from_str_radix;
+
+use super::InterpreterDirection;
+
+pub static VERSION: uint = 1;
+static STORAGE_COUNT: uint = 28;
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct Snapshot {
+    pub source_hash: u64,
+    pub counter: (int, int),
+    pub direction: InterpreterDirection,
+    pub last_move: (int, int),
+    pub storage_index: uint,
+    pub reflected: bool,
+    pub underflowed: bool,
+    /// `Storage::values` of every storage.
+    pub storages: Vec<Vec<int>>,
+}
+
+impl Snapshot {
+    pub fn write(&self, out: &mut Writer) -> IoResult<()> {
+        let (row, col) = self.counter;
+        let (row_move, col_move) = self.last_move;
+        try!(writeln!(out, "aheui-snapshot {}", VERSION));
+        try!(writeln!(out, "source {:016x}", self.source_hash));
+        try!(writeln!(out, "counter {} {}", row, col));
+        try!(writeln!(out, "direction {}", self.direction.name()));
+        try!(writeln!(out, "last-move {} {}", row_move, col_move));
+        try!(writeln!(out, "storage {}", self.storage_index));
+        try!(writeln!(out, "reflected {}", self.reflected));
+        try!(writeln!(out, "underflowed {}", self.underflowed));
+        for (index, values) in self.storages.iter().enumerate() {
+            if values.is_empty() {
+                continue;
+            }
+            try!(write!(out, "values {}", index));
+            for v in values.iter() {
+                try!(write!(out, " {}", v));
+            }
+            try!(writeln!(out, ""));
+        }
+        Ok(())
+    }
+
+    pub fn to_str(&self) -> String {
+        let mut out = ::std::io::MemWriter::new();
+        let _ = self.write(&mut out);
+        String::from_utf8(out.unwrap()).unwrap()
+    }
+
+    pub fn from_str(s: &str) -> Result<Snapshot, String> {
+        let mut lines = s.lines().enumerate();
+        match lines.next() {
+            Some((_, line)) if line.trim() == format!("aheui-snapshot {}", VERSION).as_slice() => { }
+            _ => { return Err(String::from_str("not an aheui snapshot of a known version")); }
+        }
+
+        let mut snapshot = Snapshot {
+            source_hash: 0,
+            counter: (0, 0),
+            direction: super::Down,
+            last_move: (1, 0),
+            storage_index: 0,
+            reflected: false,
+            underflowed: false,
+            storages: Vec::from_fn(STORAGE_COUNT, |_| Vec::new()),
+        };
+        let mut seen_source = false;
+        for (idx, line) in lines {
+            let words: Vec<&str> = line.words().collect();
+            let error = || format!("line {}: invalid `{}`", idx + 1, line.trim());
+            if words.is_empty() {
+                continue;
+            }
+            let args = words.slice_from(1);
+            match (*words.get(0), args) {
+                ("source", [hash]) => {
+                    snapshot.source_hash = match from_str_radix::<u64>(hash, 16) {
+                        Some(hash) => hash,
+                        None => { return Err(error()); }
+                    };
+                    seen_source = true;
+                }
+                ("counter", [row, col]) => {
+                    snapshot.counter = match (from_str::<int>(row), from_str::<int>(col)) {
+                        (Some(row), Some(col)) => (row, col),
+                        _ => { return Err(error()); }
+                    };
+                }
+                ("direction", [name]) => {
+                    snapshot.direction = match InterpreterDirection::from_name(name) {
+                        Some(direction) => direction,
+                        None => { return Err(error()); }
+                    };
+                }
+                ("last-move", [row, col]) => {
+                    snapshot.last_move = match (from_str::<int>(row), from_str::<int>(col)) {
+                        (Some(row), Some(col)) => (row, col),
+                        _ => { return Err(error()); }
+                    };
+                }
+                ("storage", [index]) => {
+                    snapshot.storage_index = match from_str::<uint>(index) {
+                        Some(index) if index < STORAGE_COUNT => index,
+                        _ => { return Err(error()); }
+                    };
+                }
+                ("reflected", [flag]) => {
+                    snapshot.reflected = match from_str::<bool>(flag) {
+                        Some(flag) => flag,
+                        None => { return Err(error()); }
+                    };
+                }
+                ("underflowed", [flag]) => {
+                    snapshot.underflowed = match from_str::<bool>(flag) {
+                        Some(flag) => flag,
+                        None => { return Err(error()); }
+                    };
+                }
+                ("values", [index, ..values]) => {
+                    let index = match from_str::<uint>(index) {
+                        Some(index) if index < STORAGE_COUNT => index,
+                        _ => { return Err(error()); }
+                    };
+                    let mut parsed = Vec::with_capacity(values.len());
+                    for v in values.iter() {
+                        match from_str::<int>(*v) {
+                            Some(v) => { parsed.push(v); }
+                            None => { return Err(error()); }
+                        }
+                    }
+                    *snapshot.storages.get_mut(index) = parsed;
+                }
+                _ => { return Err(error()); }
+            }
+        }
+        if !seen_source {
+            return Err(String::from_str("no source hash"));
+        }
+        Ok(snapshot)
+    }
+}
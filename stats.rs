@@ -0,0 +1,120 @@
+//! Size and composition of a program, without running it.
+
+use std::cmp;
+use std::collections::HashMap;
+use std::io::IoResult;
+
+use super::{Source, Instruction, ChangeStorageOperation, MoveToStorageOperation};
+use super::{RegularMovement, AllowHorizontalMovement, AllowVerticalMovement, DisallowMovement};
+use super::{KeepCurrentMovement, WallMovement};
+use usage::Usage;
+
+pub struct Stats {
+    pub rows: uint,
+    /// Cells in the longest row.
+    pub columns: uint,
+    pub hangeul_cells: uint,
+    pub other_cells: uint,
+    pub utf8_bytes: uint,
+    pub utf16_bytes: uint,
+    /// Syllables by operation, the most frequent first.
+    pub operations: Vec<(String, uint)>,
+    /// Syllables by the movement of their vowel, the most frequent first.
+    pub movements: Vec<(String, uint)>,
+    /// Storages selected or moved to somewhere, and storage 0 when the program
+    /// touches it before selecting another, in increasing order.
+    pub storages: Vec<uint>,
+}
+
+impl Stats {
+    pub fn from_source(source: &Source) -> Stats {
+        let mut stats = Stats {
+            rows: source.height(),
+            columns: 0,
+            hangeul_cells: 0,
+            other_cells: 0,
+            // Newlines between the rows.
+            utf8_bytes: if source.height() > 0 { source.height() - 1 } else { 0 },
+            utf16_bytes: if source.height() > 0 { 2 * (source.height() - 1) } else { 0 },
+            operations: Vec::new(),
+            movements: Vec::new(),
+            storages: Vec::new(),
+        };
+        let mut operations = HashMap::new();
+        let mut movements = HashMap::new();
+        for row in range(0, source.height()) {
+            stats.columns = cmp::max(stats.columns, source.row_len(row));
+            for col in range(0, source.row_len(row)) {
+                let instruction = source.get((row as int, col as int));
+                let c = instruction.char().unwrap();
+                stats.utf8_bytes += c.len_utf8_bytes();
+                stats.utf16_bytes += if (c as u32) > 0xffff { 4 } else { 2 };
+                if !instruction.is_hangeul() {
+                    stats.other_cells += 1;
+                    continue;
+                }
+                stats.hangeul_cells += 1;
+                let operation = match instruction.binary_operator() {
+                    Some(operator) => String::from_str(operator),
+                    None => String::from_str(instruction.operation().name()),
+                };
+                operations.insert_or_update_with(operation, 1u, |_, count| *count += 1);
+                movements.insert_or_update_with(movement_name(&instruction), 1u, |_, count| *count += 1);
+                match instruction.operation() {
+                    ChangeStorageOperation(index) | MoveToStorageOperation(index) => {
+                        if !stats.storages.contains(&index) {
+                            stats.storages.push(index);
+                        }
+                    }
+                    _ => { }
+                }
+            }
+        }
+        stats.operations = histogram(operations);
+        stats.movements = histogram(movements);
+        for index in Usage::from_source(source).used().move_iter() {
+            if !stats.storages.contains(&index) {
+                stats.storages.push(index);
+            }
+        }
+        stats.storages.sort();
+        stats
+    }
+
+    pub fn write(&self, out: &mut Writer) -> IoResult<()> {
+        try!(writeln!(out, "size: {} rows, {} columns", self.rows, self.columns));
+        try!(writeln!(out, "cells: {} hangul, {} other", self.hangeul_cells, self.other_cells));
+        try!(writeln!(out, "bytes: {} UTF-8, {} UTF-16", self.utf8_bytes, self.utf16_bytes));
+        try!(writeln!(out, "operations:"));
+        for &(ref name, count) in self.operations.iter() {
+            try!(writeln!(out, "    {:<12} {:5}", name.as_slice(), count));
+        }
+        try!(writeln!(out, "movements:"));
+        for &(ref name, count) in self.movements.iter() {
+            try!(writeln!(out, "    {:<12} {:5}", name.as_slice(), count));
+        }
+        let storages: Vec<String> = self.storages.iter().map(|index| format!("{}", index)).collect();
+        writeln!(out, "storages: {}{}", self.storages.len(),
+                 if storages.is_empty() { String::new() } else { format!(" ({})", storages.connect(", ")) })
+    }
+}
+
+/// `right 1`, `up 2`, `reflect vertical` and so on.
+fn movement_name(instruction: &Instruction) -> String {
+    match instruction.move {
+        RegularMovement(direction, row, col) => format!("{} {}", direction.name(), if row != 0 { row.abs() } else { col.abs() }),
+        AllowHorizontalMovement => String::from_str("reflect vertical"),
+        AllowVerticalMovement => String::from_str("reflect horizontal"),
+        DisallowMovement => String::from_str("reflect"),
+        KeepCurrentMovement => String::from_str("keep"),
+        WallMovement(..) => String::from_str("wall"),
+    }
+}
+
+fn histogram(counts: HashMap<String, uint>) -> Vec<(String, uint)> {
+    let mut entries: Vec<(String, uint)> = counts.move_iter().collect();
+    entries.sort_by(|&(ref name_a, count_a), &(ref name_b, count_b)| {
+        if count_a != count_b { count_b.cmp(&count_a) } else { name_a.cmp(name_b) }
+    });
+    entries
+}
@@ -0,0 +1,200 @@
+//! Removal of the parts of a program which never run.
+//!
+//! Syllables `Reachability` rules out are replaced by blanks, then blanks at
+//! the end of rows and blank rows at the end of the program are trimmed. A
+//! trim is kept only if every syllable still leads to the same syllables in
+//! the same states, walls included, so that wrapping around behaves as
+//! before. The trim of each row is searched for by bisection: it is always
+//! safe, but as wrapping around makes a shorter trim unsafe where a longer
+//! one is safe at times, it may not be the longest safe one. `verify` then
+//! runs both programs to compare their output.
+
+use std::collections::HashMap;
+use std::io::{MemReader, ChanWriter};
+use std::io::util::NullWriter;
+use std::task::TaskBuilder;
+
+use super::{Source, Interpreter, Halted};
+use graph::{Graph, State, Goto};
+use reachability::Reachability;
+
+/// Replaces unreachable syllables; an ideographic space is as wide as a
+/// syllable, so the columns stay aligned.
+pub static BLANK: char = '　';
+
+/// Steps `verify` runs each program for when nothing else is given.
+pub static DEFAULT_STEPS: uint = 1000000;
+
+pub struct Stripped {
+    pub text: String,
+    /// Syllables replaced by `BLANK`.
+    pub blanked: uint,
+    /// Cells trimmed from the ends of the rows, and rows removed.
+    pub trimmed_cells: uint,
+    pub trimmed_rows: uint,
+}
+
+pub fn strip(source: &Source) -> Stripped {
+    let reachability = Reachability::from_source(source);
+    let mut stripped = Stripped { text: String::new(), blanked: 0, trimmed_cells: 0, trimmed_rows: 0 };
+    let mut grid: Vec<Vec<char>> = Vec::new();
+    for row in range(0, source.height()) {
+        let mut cells = Vec::new();
+        for col in range(0, source.row_len(row)) {
+            let position = (row as int, col as int);
+            let instruction = source.get(position);
+            if instruction.is_hangeul() && !reachability.reachable(position) {
+                cells.push(BLANK);
+                stripped.blanked += 1;
+            } else {
+                cells.push(instruction.char().unwrap());
+            }
+        }
+        grid.push(cells);
+    }
+
+    let expected = behaviour(source);
+    for row in range(0, grid.len()) {
+        let blanks = grid.get(row).iter().rev().take_while(|c| c.is_whitespace()).count();
+        // `safe` cells can be trimmed; more than `most` are not tried.
+        let mut safe = 0;
+        let mut most = blanks;
+        while safe < most {
+            let trim = (safe + most + 1) / 2;
+            if behaviour(&Source::from_str(text(&trimmed(&grid, row, trim)).as_slice())) == expected {
+                safe = trim;
+            } else {
+                most = trim - 1;
+            }
+        }
+        grid = trimmed(&grid, row, safe);
+        stripped.trimmed_cells += safe;
+    }
+    while grid.len() > 1 && grid.last().unwrap().iter().all(|c| c.is_whitespace()) {
+        let mut candidate = grid.clone();
+        candidate.pop();
+        if behaviour(&Source::from_str(text(&candidate).as_slice())) != expected {
+            break;
+        }
+        stripped.trimmed_cells += grid.last().unwrap().len();
+        stripped.trimmed_rows += 1;
+        grid = candidate;
+    }
+
+    stripped.text = text(&grid);
+    stripped
+}
+
+fn trimmed(grid: &Vec<Vec<char>>, row: uint, trim: uint) -> Vec<Vec<char>> {
+    let mut grid = grid.clone();
+    let len = grid.get(row).len();
+    grid.get_mut(row).truncate(len - trim);
+    grid
+}
+
+fn text(grid: &Vec<Vec<char>>) -> String {
+    let rows: Vec<String> = grid.iter().map(|cells| String::from_chars(cells.as_slice())).collect();
+    rows.connect("\n")
+}
+
+/// The first state executing a syllable, and for each such state the next
+/// ones on the regular and reflected edges, walls and other characters
+/// passed.
+fn behaviour(source: &Source) -> (Option<State>, HashMap<State, (Option<State>, Option<State>)>) {
+    let graph = Graph::from_source(source);
+    let mut transitions = HashMap::new();
+    let resolved = |idx: uint| resolve(&graph, idx).map(|idx| graph.nodes.get(idx).state);
+    for node in graph.nodes.iter() {
+        if !node.instruction.is_hangeul() {
+            continue;
+        }
+        let next = match node.next {
+            Goto(next) => resolved(next),
+            _ => None,
+        };
+        let reflected = match node.reflected {
+            Some(Goto(next)) => resolved(next),
+            _ => None,
+        };
+        transitions.insert(node.state, (next, reflected));
+    }
+    let start = if graph.len() > 0 { resolved(0) } else { None };
+    (start, transitions)
+}
+
+fn resolve(graph: &Graph, idx: uint) -> Option<uint> {
+    let mut idx = idx;
+    for _ in range(0, graph.len()) {
+        let node = graph.nodes.get(idx);
+        if node.instruction.is_hangeul() {
+            return Some(idx);
+        }
+        idx = match node.next {
+            Goto(next) => next,
+            _ => { return None; }
+        };
+    }
+    None
+}
+
+/// How a run given a number of steps ended.
+#[deriving(PartialEq, Show)]
+enum Ending {
+    Halts,
+    Runs,
+    /// The interpreter failed, reading past the input or dividing by zero
+    /// for instance.
+    Fails,
+}
+
+impl Ending {
+    fn describe(&self) -> &'static str {
+        match *self {
+            Halts => "halts",
+            Runs => "does not halt",
+            Fails => "fails",
+        }
+    }
+}
+
+/// Runs both programs on `input` for up to `max_steps` steps each, failing
+/// if their output differs or they do not end the same way.
+pub fn verify(original: &str, stripped: &str, input: &[u8], max_steps: uint) -> Result<(), String> {
+    let (before, ending_before) = output(original, input, max_steps);
+    let (after, ending_after) = output(stripped, input, max_steps);
+    if ending_before != ending_after {
+        return Err(format!("the original program {} within {} steps, but the stripped one {}",
+                           ending_before.describe(), max_steps, ending_after.describe()));
+    }
+    if before != after {
+        return Err(String::from_str("the stripped program prints something else"));
+    }
+    Ok(())
+}
+
+/// Runs a program in a task of its own, so that the interpreter failing
+/// ends the run like halting does.
+fn output(text: &str, input: &[u8], max_steps: uint) -> (Vec<u8>, Ending) {
+    let (tx, rx) = channel();
+    let source = Source::from_str(text);
+    let input = Vec::from_slice(input);
+    let result = TaskBuilder::new().stderr(box NullWriter).try(proc() {
+        let mut interpreter = Interpreter::with_io(source, box MemReader::new(input), box ChanWriter::new(tx));
+        let halted = interpreter.run_steps(max_steps) == Some(Halted);
+        interpreter.flush();
+        halted
+    });
+    let ending = match result {
+        Ok(true) => Halts,
+        Ok(false) => Runs,
+        Err(_) => Fails,
+    };
+    let mut out = Vec::new();
+    loop {
+        match rx.recv_opt() {
+            Ok(bytes) => { out.push_all(bytes.as_slice()); }
+            Err(()) => { break; }
+        }
+    }
+    (out, ending)
+}
@@ -0,0 +1,631 @@
+//! Symbolic execution: runs a program on unknown inputs to find inputs
+//! reaching each branch outcome and halt.
+//!
+//! Every value read by `PushIntegerInputOperation` or `PushCharInputOperation`
+//! becomes a variable, and the storages hold linear expressions of them. A
+//! branch or a comparison on such an expression forks the path, adding a
+//! constraint to each side; `solve` keeps a concrete input satisfying the
+//! constraints of every path and drops the paths it proves have none. Paths
+//! it gives up on are dropped too, and leave the exploration incomplete.
+//!
+//! Products of two inputs, divisions and remainders of inputs are not
+//! linear and become `Opaque`. Branching on them follows both sides without
+//! a constraint, and the inputs found on such paths are marked inexact: they
+//! may take the other side when run. Dividing by an input constrains it to
+//! be nonzero, as the interpreter fails otherwise; dividing by an opaque
+//! value makes the path inexact.
+
+use std::char;
+use std::collections::HashMap;
+
+use super::{Source, HaltOperation, BranchOperation, CompareOperation, BinaryOperation};
+use super::{PushConstantOperation, PushDuplicationOperation, PushIntegerInputOperation, PushCharInputOperation};
+use super::{PopOperation, PrintIntegerOperation, PrintCharOperation, SwapOperation};
+use super::{ChangeStorageOperation, MoveToStorageOperation, NoOperation};
+use graph::{Graph, Edge, Goto};
+
+pub static DEFAULT_PATHS: uint = 1000;
+pub static DEFAULT_STEPS: uint = 10000;
+
+/// Integer inputs are looked for between -INPUT_BOUND and INPUT_BOUND.
+static INPUT_BOUND: int = 1 << 31;
+static MAX_CHAR: int = 0x10ffff;
+/// Values tried for a variable before giving up on it.
+static CANDIDATES: uint = 64;
+/// Assignments `solve` tries before giving up.
+static SOLVER_BUDGET: uint = 10000;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum InputKind {
+    IntegerInput,
+    CharInput,
+}
+
+impl InputKind {
+    fn bounds(&self) -> (int, int) {
+        match *self {
+            IntegerInput => (-INPUT_BOUND, INPUT_BOUND),
+            CharInput => (0, MAX_CHAR),
+        }
+    }
+
+    /// Value tried first, so that inputs without constraints look ordinary.
+    fn preferred(&self) -> int {
+        match *self {
+            IntegerInput => 0,
+            CharInput => 'a' as int,
+        }
+    }
+}
+
+/// `constant` plus the sum of coefficient times input for each term. Terms
+/// are sorted by input and have no zero coefficient.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Linear {
+    pub constant: int,
+    pub terms: Vec<(uint, int)>,
+}
+
+impl Linear {
+    pub fn constant(v: int) -> Linear {
+        Linear { constant: v, terms: Vec::new() }
+    }
+
+    pub fn input(index: uint) -> Linear {
+        Linear { constant: 0, terms: vec!((index, 1)) }
+    }
+
+    pub fn constant_value(&self) -> Option<int> {
+        if self.terms.is_empty() { Some(self.constant) } else { None }
+    }
+
+    /// `self + other * factor`.
+    pub fn combine(&self, other: &Linear, factor: int) -> Linear {
+        let mut terms = self.terms.clone();
+        for &(input, coefficient) in other.terms.iter() {
+            match terms.iter().position(|&(i, _)| i == input) {
+                Some(idx) => {
+                    let (_, c) = *terms.get(idx);
+                    *terms.get_mut(idx) = (input, c + coefficient * factor);
+                }
+                None => { terms.push((input, coefficient * factor)); }
+            }
+        }
+        terms.retain(|&(_, c)| c != 0);
+        terms.sort();
+        Linear { constant: self.constant + other.constant * factor, terms: terms }
+    }
+
+    pub fn scale(&self, factor: int) -> Linear {
+        Linear::constant(0).combine(self, factor)
+    }
+
+    pub fn evaluate(&self, inputs: &[int]) -> int {
+        self.terms.iter().fold(self.constant, |sum, &(input, c)| sum + c * inputs[input])
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Value {
+    Known(Linear),
+    /// Depends on the inputs in a way the solver cannot express.
+    Opaque,
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Relation {
+    EqualZero,
+    NonZero,
+    NonNegative,
+    Negative,
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct Constraint {
+    pub expr: Linear,
+    pub relation: Relation,
+}
+
+impl Constraint {
+    fn holds(relation: Relation, v: int) -> bool {
+        match relation {
+            EqualZero => v == 0,
+            NonZero => v != 0,
+            NonNegative => v >= 0,
+            Negative => v < 0,
+        }
+    }
+
+    pub fn satisfied(&self, inputs: &[int]) -> bool {
+        Constraint::holds(self.relation, self.expr.evaluate(inputs))
+    }
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Solution {
+    /// Inputs satisfying every constraint.
+    Sat(Vec<int>),
+    /// No inputs satisfy the constraints.
+    Unsat,
+    /// The search gave up before finding inputs or ruling them out.
+    Unknown,
+}
+
+/// Inputs of the given kinds satisfying every constraint, found by trying
+/// values near the preferred ones and propagating constraints with a single
+/// unknown input.
+pub fn solve(constraints: &[Constraint], kinds: &[InputKind]) -> Solution {
+    let bounds = kinds.iter().map(|kind| kind.bounds()).collect();
+    let mut budget = SOLVER_BUDGET;
+    search(constraints, kinds, bounds, Vec::from_elem(kinds.len(), None), &mut budget)
+}
+
+fn search(constraints: &[Constraint], kinds: &[InputKind], bounds: Vec<(int, int)>, values: Vec<Option<int>>,
+          budget: &mut uint) -> Solution {
+    if *budget == 0 {
+        return Unknown;
+    }
+    *budget -= 1;
+    let mut bounds = bounds;
+    let mut values = values;
+
+    // Narrow the inputs through the constraints on a single unknown one
+    // until nothing changes.
+    let mut excluded: Vec<Vec<int>> = Vec::from_elem(kinds.len(), Vec::new());
+    let mut changed = true;
+    while changed {
+        changed = false;
+        excluded = Vec::from_elem(kinds.len(), Vec::new());
+        for constraint in constraints.iter() {
+            let mut rest = constraint.expr.constant;
+            let mut unknown = Vec::new();
+            for &(input, c) in constraint.expr.terms.iter() {
+                match *values.get(input) {
+                    Some(v) => { rest += c * v; }
+                    None => { unknown.push((input, c)); }
+                }
+            }
+            match unknown.as_slice() {
+                [] => {
+                    if !Constraint::holds(constraint.relation, rest) {
+                        return Unsat;
+                    }
+                }
+                [(input, c)] => {
+                    let (lo, hi) = *bounds.get(input);
+                    let (new_lo, new_hi) = match constraint.relation {
+                        EqualZero => {
+                            if rest % c != 0 || -rest / c < lo || -rest / c > hi {
+                                return Unsat;
+                            }
+                            *values.get_mut(input) = Some(-rest / c);
+                            changed = true;
+                            continue;
+                        }
+                        NonZero => {
+                            if rest % c == 0 {
+                                excluded.get_mut(input).push(-rest / c);
+                            }
+                            (lo, hi)
+                        }
+                        NonNegative if c > 0 => (cmp_max(lo, ceil_div(-rest, c)), hi),
+                        NonNegative => (lo, cmp_min(hi, floor_div(rest, -c))),
+                        Negative if c > 0 => (lo, cmp_min(hi, floor_div(-rest - 1, c))),
+                        Negative => (cmp_max(lo, ceil_div(rest + 1, -c)), hi),
+                    };
+                    if new_lo > new_hi {
+                        return Unsat;
+                    }
+                    if (new_lo, new_hi) != (lo, hi) {
+                        *bounds.get_mut(input) = (new_lo, new_hi);
+                        changed = true;
+                    }
+                }
+                _ => { }
+            }
+        }
+    }
+
+    let input = match values.iter().position(|v| v.is_none()) {
+        Some(input) => input,
+        None => { return Sat(values.iter().map(|v| v.unwrap()).collect()); }
+    };
+    let (lo, hi) = *bounds.get(input);
+    let preferred = cmp_min(cmp_max(kinds[input].preferred(), lo), hi);
+    let mut candidates = vec!(preferred);
+    let mut offset = 1;
+    while candidates.len() < CANDIDATES && (preferred + offset <= hi || preferred - offset >= lo) {
+        if preferred + offset <= hi { candidates.push(preferred + offset); }
+        if preferred - offset >= lo { candidates.push(preferred - offset); }
+        offset += 1;
+    }
+    // Values left untried may still satisfy the constraints.
+    let mut unknown = candidates.len() as int <= hi - lo;
+    for v in candidates.iter() {
+        if excluded.get(input).contains(v) {
+            continue;
+        }
+        let mut next = values.clone();
+        *next.get_mut(input) = Some(*v);
+        match search(constraints, kinds, bounds.clone(), next, budget) {
+            Sat(solution) => { return Sat(solution); }
+            Unknown => { unknown = true; }
+            Unsat => { }
+        }
+    }
+    if unknown { Unknown } else { Unsat }
+}
+
+fn cmp_min(a: int, b: int) -> int { if a < b { a } else { b } }
+fn cmp_max(a: int, b: int) -> int { if a > b { a } else { b } }
+
+/// Quotient rounded down, for a positive `b`.
+fn floor_div(a: int, b: int) -> int {
+    if a % b != 0 && a < 0 { a / b - 1 } else { a / b }
+}
+
+fn ceil_div(a: int, b: int) -> int {
+    -floor_div(-a, b)
+}
+
+#[deriving(Clone, PartialEq, Eq, Hash, Show)]
+pub enum Target {
+    /// A branch at a position, taking the nonzero side if true. An empty
+    /// storage counts as the zero side, as both reflect.
+    BranchTarget((int, int), bool),
+    HaltTarget((int, int)),
+}
+
+/// Inputs found to reach a target.
+pub struct Witness {
+    pub target: Target,
+    pub inputs: Vec<int>,
+    pub kinds: Vec<InputKind>,
+    /// Whether no opaque value was branched on before the target.
+    pub exact: bool,
+}
+
+impl Witness {
+    /// The inputs as the interpreter reads them: a line per integer and a
+    /// character per character.
+    pub fn input_text(&self) -> String {
+        let mut text = String::new();
+        for (v, kind) in self.inputs.iter().zip(self.kinds.iter()) {
+            match *kind {
+                IntegerInput => { text.push_str(format!("{}\n", v).as_slice()); }
+                CharInput => { text.push_char(char::from_u32(*v as u32).unwrap_or('?')); }
+            }
+        }
+        text
+    }
+}
+
+pub struct Exploration {
+    /// A witness per target reached, in the order they were found.
+    pub witnesses: Vec<Witness>,
+    pub paths: uint,
+    /// Whether every path was followed to its end within the limits, and the
+    /// solver decided every constraint.
+    pub complete: bool,
+    found: HashMap<Target, uint>,
+}
+
+impl Exploration {
+    pub fn witness(&self, target: Target) -> Option<&Witness> {
+        self.found.find(&target).map(|idx| self.witnesses.get(*idx))
+    }
+
+    fn record(&mut self, target: Target, path: &PathState) {
+        let witness = Witness {
+            target: target,
+            inputs: path.solution.clone(),
+            kinds: path.kinds.clone(),
+            exact: path.exact,
+        };
+        match self.found.find(&target) {
+            Some(idx) => {
+                if path.exact && !self.witnesses.get(*idx).exact {
+                    *self.witnesses.get_mut(*idx) = witness;
+                }
+                return;
+            }
+            None => { }
+        }
+        self.found.insert(target, self.witnesses.len());
+        self.witnesses.push(witness);
+    }
+}
+
+#[deriving(Clone)]
+struct PathState {
+    node: uint,
+    steps: uint,
+    storage_index: uint,
+    storages: Vec<Vec<Value>>,
+    constraints: Vec<Constraint>,
+    kinds: Vec<InputKind>,
+    /// Inputs satisfying `constraints`.
+    solution: Vec<int>,
+    exact: bool,
+}
+
+impl PathState {
+    fn is_queue(&self) -> bool {
+        self.storage_index == 21 || self.storage_index == 27
+    }
+
+    fn len(&self) -> uint {
+        self.storages.get(self.storage_index).len()
+    }
+
+    fn put(&mut self, value: Value) {
+        self.storages.get_mut(self.storage_index).push(value);
+    }
+
+    fn rput(&mut self, value: Value) {
+        let queue = self.is_queue();
+        let storage = self.storages.get_mut(self.storage_index);
+        if queue { storage.insert(0, value); } else { storage.push(value); }
+    }
+
+    fn pick(&mut self) -> Option<Value> {
+        let queue = self.is_queue();
+        let storage = self.storages.get_mut(self.storage_index);
+        if queue { storage.remove(0) } else { storage.pop() }
+    }
+
+    fn peek(&self) -> Option<Value> {
+        let storage = self.storages.get(self.storage_index);
+        if self.is_queue() { storage.as_slice().head().map(|v| v.clone()) } else { storage.last().map(|v| v.clone()) }
+    }
+
+    fn read(&mut self, kind: InputKind) {
+        let index = self.kinds.len();
+        self.kinds.push(kind);
+        self.solution.push(kind.preferred());
+        self.put(Known(Linear::input(index)));
+    }
+
+    /// Adds a constraint, returning false if no input satisfies them all or
+    /// if the solver gives up, which leaves `exploration` incomplete.
+    fn constrain(&mut self, expr: Linear, relation: Relation, exploration: &mut Exploration) -> bool {
+        let constraint = Constraint { expr: expr, relation: relation };
+        let satisfied = constraint.satisfied(self.solution.as_slice());
+        self.constraints.push(constraint);
+        if satisfied {
+            return true;
+        }
+        match solve(self.constraints.as_slice(), self.kinds.as_slice()) {
+            Sat(solution) => {
+                self.solution = solution;
+                true
+            }
+            Unsat => false,
+            Unknown => {
+                exploration.complete = false;
+                false
+            }
+        }
+    }
+
+    fn follow(self, edge: Option<Edge>) -> Vec<PathState> {
+        let mut path = self;
+        match edge {
+            Some(Goto(next)) => {
+                path.node = next;
+                vec!(path)
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Executes the node of the path, returning the paths which continue
+    /// from it: none if it ends, two if it forks.
+    fn step(self, graph: &Graph, exploration: &mut Exploration) -> Vec<PathState> {
+        let mut path = self;
+        path.steps += 1;
+        let node = graph.nodes.get(path.node);
+        let position = node.state.position();
+        match node.instruction.operation() {
+            HaltOperation => {
+                exploration.record(HaltTarget(position), &path);
+                Vec::new()
+            }
+            BranchOperation => {
+                let value = match path.pick() {
+                    Some(value) => value,
+                    None => {
+                        exploration.record(BranchTarget(position, false), &path);
+                        return path.follow(node.reflected);
+                    }
+                };
+                let mut paths = Vec::new();
+                for &nonzero in [true, false].iter() {
+                    let mut side = path.clone();
+                    let feasible = match value {
+                        Known(ref expr) => match expr.constant_value() {
+                            Some(v) => (v != 0) == nonzero,
+                            None => side.constrain(expr.clone(), if nonzero { NonZero } else { EqualZero }, exploration),
+                        },
+                        Opaque => {
+                            side.exact = false;
+                            true
+                        }
+                    };
+                    if feasible {
+                        exploration.record(BranchTarget(position, nonzero), &side);
+                        paths.push_all_move(side.follow(if nonzero { Some(node.next) } else { node.reflected }));
+                    }
+                }
+                paths
+            }
+            CompareOperation => {
+                if path.len() < 2 {
+                    return path.follow(node.reflected);
+                }
+                let v1 = path.pick().unwrap();
+                let v2 = path.pick().unwrap();
+                let difference = match (v1, v2) {
+                    (Known(a), Known(b)) => Known(b.combine(&a, -1)),
+                    _ => Opaque,
+                };
+                let mut paths = Vec::new();
+                for &greater_or_equal in [true, false].iter() {
+                    let mut side = path.clone();
+                    let feasible = match difference {
+                        Known(ref expr) => match expr.constant_value() {
+                            Some(v) => (v >= 0) == greater_or_equal,
+                            None => side.constrain(expr.clone(), if greater_or_equal { NonNegative } else { Negative }, exploration),
+                        },
+                        Opaque => {
+                            side.exact = false;
+                            true
+                        }
+                    };
+                    if feasible {
+                        side.put(Known(Linear::constant(if greater_or_equal { 1 } else { 0 })));
+                        paths.push_all_move(side.follow(Some(node.next)));
+                    }
+                }
+                paths
+            }
+            BinaryOperation(op) => {
+                if path.len() < 2 {
+                    return path.follow(node.reflected);
+                }
+                let v1 = path.pick().unwrap();
+                let v2 = path.pick().unwrap();
+                let name = node.instruction.binary_operator().unwrap();
+                if name == "div" || name == "mod" {
+                    match v1 {
+                        // Only the side with a nonzero divisor goes on.
+                        Known(ref divisor) if divisor.constant_value().is_none() => {
+                            if !path.constrain(divisor.clone(), NonZero, exploration) {
+                                return Vec::new();
+                            }
+                        }
+                        Opaque => { path.exact = false; }
+                        _ => { }
+                    }
+                }
+                match binary(name, op, v1, v2) {
+                    Some(result) => {
+                        path.put(result);
+                        path.follow(Some(node.next))
+                    }
+                    // The interpreter fails dividing by zero.
+                    None => Vec::new(),
+                }
+            }
+            PushConstantOperation(v) => {
+                path.put(Known(Linear::constant(v)));
+                path.follow(Some(node.next))
+            }
+            PushDuplicationOperation => match path.peek() {
+                Some(value) => {
+                    path.put(value);
+                    path.follow(Some(node.next))
+                }
+                None => path.follow(node.reflected),
+            },
+            PushIntegerInputOperation => {
+                path.read(IntegerInput);
+                path.follow(Some(node.next))
+            }
+            PushCharInputOperation => {
+                path.read(CharInput);
+                path.follow(Some(node.next))
+            }
+            PopOperation | PrintIntegerOperation | PrintCharOperation => match path.pick() {
+                Some(_) => path.follow(Some(node.next)),
+                None => path.follow(node.reflected),
+            },
+            SwapOperation => {
+                if path.len() < 2 {
+                    return path.follow(node.reflected);
+                }
+                let v1 = path.pick().unwrap();
+                let v2 = path.pick().unwrap();
+                path.rput(v1);
+                path.rput(v2);
+                path.follow(Some(node.next))
+            }
+            MoveToStorageOperation(index) => match path.pick() {
+                Some(value) => {
+                    path.storage_index = index;
+                    path.put(value);
+                    path.follow(Some(node.next))
+                }
+                None => path.follow(node.reflected),
+            },
+            ChangeStorageOperation(index) => {
+                path.storage_index = index;
+                path.follow(Some(node.next))
+            }
+            NoOperation => path.follow(Some(node.next)),
+        }
+    }
+}
+
+/// Result of a binary operation whose top operand is `v1`, or None if it
+/// divides by zero.
+fn binary(name: &str, op: fn(int, int) -> int, v1: Value, v2: Value) -> Option<Value> {
+    let (a, b) = match (v1, v2) {
+        (Known(a), Known(b)) => (a, b),
+        _ => { return Some(Opaque); }
+    };
+    match (a.constant_value(), b.constant_value(), name) {
+        (Some(0), _, "div") | (Some(0), _, "mod") => None,
+        (Some(x), Some(y), _) => Some(Known(Linear::constant(op(x, y)))),
+        (_, _, "add") => Some(Known(b.combine(&a, 1))),
+        (_, _, "sub") => Some(Known(b.combine(&a, -1))),
+        (Some(k), _, "mul") => Some(Known(b.scale(k))),
+        (_, Some(k), "mul") => Some(Known(a.scale(k))),
+        _ => Some(Opaque),
+    }
+}
+
+/// Follows the paths of `source` depth first, up to `max_paths` of them and
+/// `max_steps` steps along each.
+pub fn explore(source: &Source, max_paths: uint, max_steps: uint) -> Exploration {
+    let graph = Graph::from_source(source);
+    let mut exploration = Exploration { witnesses: Vec::new(), paths: 0, complete: true, found: HashMap::new() };
+    if graph.len() == 0 {
+        return exploration;
+    }
+    let start = PathState {
+        node: 0,
+        steps: 0,
+        storage_index: 0,
+        storages: Vec::from_elem(28, Vec::new()),
+        constraints: Vec::new(),
+        kinds: Vec::new(),
+        solution: Vec::new(),
+        exact: true,
+    };
+    let mut pending = vec!(start);
+    while !pending.is_empty() {
+        if exploration.paths >= max_paths {
+            exploration.complete = false;
+            break;
+        }
+        exploration.paths += 1;
+        let mut path = pending.pop().unwrap();
+        loop {
+            if path.steps >= max_steps {
+                exploration.complete = false;
+                break;
+            }
+            let mut next = path.step(&graph, &mut exploration);
+            if next.is_empty() {
+                break;
+            }
+            // Carry on with the first side and come back for the other.
+            while next.len() > 1 {
+                pending.push(next.pop().unwrap());
+            }
+            path = next.pop().unwrap();
+        }
+    }
+    exploration
+}
@@ -1,8 +1,10 @@
 #![license="BSD simplified"]
-#![feature(macro_rules)]
+#![feature(macro_rules, phase)]
 
 extern crate hangeul;
 extern crate aheui;
+#[phase(syntax)]
+extern crate aheui_macros;
 
 macro_rules! check_final_draw_counts(
     ($final:expr, $count:expr) => (
@@ -14,7 +16,53 @@ macro_rules! check_final_draw_counts(
 mod tests {
     use hangeul;
     use aheui;
-    use aheui::{Instruction, Source, Interpreter};
+    use aheui::{Instruction, Source, Interpreter, Storage, Stack, Queue, Left};
+    use aheui::{CellBreakpoint, DirectedCellBreakpoint, OperationBreakpoint};
+    use aheui::{LengthWatchpoint, StorageSelectionWatchpoint};
+    use aheui::{Halted, Stopped, BreakpointHit, WatchpointHit, InfiniteLoop};
+    use aheui::graph::{Graph, Goto, Halt};
+    use aheui::transpile::transpile;
+    use aheui::debugger::Debugger;
+    use aheui::trace::{JsonTrace, TextTrace};
+    use aheui::snapshot::Snapshot;
+    use aheui::visualize::Visualizer;
+    use aheui::observer::InterpreterObserver;
+    use aheui::dap::Server;
+    use aheui::reachability::Reachability;
+    use aheui::lint::lint;
+    use aheui::cfg::ControlFlow;
+    use aheui::symbolic;
+    use aheui::usage::Usage;
+    use aheui::loops::Loop;
+    use aheui::strip;
+    use aheui::stats::Stats;
+    use aheui::disasm::Listing;
+    use aheui::symbolic::{Constraint, Linear, IntegerInput, EqualZero, NonNegative, Negative, BranchTarget, HaltTarget};
+    use aheui::symbolic::{Sat, Unsat, Unknown};
+    use aheui::depth::{DepthAnalysis, Never, Sometimes, Always};
+    use std::io::{File, MemReader, MemWriter, ChanWriter, TempDir};
+    use std::io::process::{Command, ProcessOutput};
+
+    aheui!(sum, "방방다망희")
+    aheui!(hello, file "hello.ah")
+
+    fn collect(rx: Receiver<Vec<u8>>) -> String {
+        let mut output = Vec::new();
+        for chunk in rx.iter() {
+            output.push_all(chunk.as_slice());
+        }
+        String::from_utf8(output).unwrap()
+    }
+
+    fn run(source: &str, input: &str) -> String {
+        let (tx, rx) = channel();
+        {
+            let reader = MemReader::new(Vec::from_slice(input.as_bytes()));
+            let mut it = Interpreter::with_io(Source::from_str(source), box reader, box ChanWriter::new(tx));
+            it.execute();
+        }
+        collect(rx)
+    }
 
     #[test]
     pub fn test_source() {
@@ -170,6 +218,44 @@ mod tests {
         assert_eq!(it.storage().peek().unwrap(), 7);
     }
 
+    #[test]
+    pub fn test_stack() {
+        let mut stack = Stack::new();
+        stack.put(1);
+        stack.put(2);
+        stack.put(3);
+        assert_eq!(stack.peek(), Some(3));
+        assert!(stack.swap());
+        assert_eq!(Some(2), stack.pick());
+        assert_eq!(Some(3), stack.pick());
+        assert_eq!(Some(1), stack.pick());
+        assert_eq!(None, stack.pick());
+        assert!(!stack.swap());
+    }
+
+    #[test]
+    pub fn test_queue_storage() {
+        let mut queue = Queue::new();
+        for i in range(0, 20) {
+            queue.put(i);
+            assert_eq!(Some(i / 2), queue.pick());
+            queue.put(i);
+        }
+        assert_eq!(queue.len(), 20);
+        queue.rput(-1);
+        assert_eq!(queue.peek(), Some(-1));
+        assert!(queue.swap());
+        assert_eq!(Some(10), queue.pick());
+        assert_eq!(Some(-1), queue.pick());
+        assert_eq!(Some(10), queue.pick());
+        for i in range(11, 20) {
+            assert_eq!(Some(i), queue.pick());
+            assert_eq!(Some(i), queue.pick());
+        }
+        assert_eq!(None, queue.pick());
+        assert_eq!(queue.len(), 0);
+    }
+
     #[test]
     pub fn test_helloworld() {
         let source = Source::from_str("밤밣따빠밣밟따뿌\n빠맣파빨받밤뚜뭏\n돋밬탕빠맣붏두붇\n볻뫃박발뚷투뭏붖\n뫃도뫃희멓뭏뭏붘\n뫃봌토범더벌뿌뚜\n뽑뽀멓멓더벓뻐뚠\n뽀덩벐멓뻐덕더벅");
@@ -287,4 +373,694 @@ mod tests {
         assert_eq!(it.storage().len(), 8);
         assert_eq!(it.storage().peek().unwrap(), 3);
     }
+
+    #[test]
+    pub fn test_graph() {
+        let graph = Graph::from_source(&Source::from_str("아희"));
+        assert_eq!(graph.len(), 2);
+        assert_eq!(graph.nodes.get(0).state.position(), (0, 0));
+        assert_eq!(graph.nodes.get(0).next, Goto(1));
+        assert!(graph.nodes.get(0).reflected.is_none());
+        assert_eq!(graph.nodes.get(1).state.position(), (0, 1));
+        assert_eq!(graph.nodes.get(1).next, Halt);
+
+        let graph = Graph::from_source(&Source::from_str("어아"));
+        assert_eq!(graph.nodes.get(1).state.position(), (0, -1));
+        assert_eq!(graph.successors(1), vec!(2));
+        assert_eq!(graph.nodes.get(2).state.position(), (0, 1));
+    }
+
+    #[test]
+    pub fn test_transpile() {
+        let code = transpile(&Source::from_str("반밧다망희"), "add");
+        assert!(code.as_slice().starts_with("#[allow("));
+        assert!(code.as_slice().contains("pub fn add<R: Buffer, W: Writer>(input: &mut R, output: &mut W) {"));
+        assert!(code.as_slice().contains("storages.get_mut(current).put(2);"));
+        assert!(code.as_slice().contains("s.put(v2 + v1);"));
+        assert!(code.as_slice().contains("return;"));
+    }
+
+    #[test]
+    pub fn test_io() {
+        assert_eq!(run("방방다망희", "3\n4\n").as_slice(), "7");
+        assert_eq!(run("밯밯망망희", "가A").as_slice(), "6544032");
+    }
+
+    #[test]
+    pub fn test_macro() {
+        let mut out = MemWriter::new();
+        sum(&mut MemReader::new(Vec::from_slice(b"3\n4\n")), &mut out);
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap(), run("방방다망희", "3\n4\n"));
+
+        let text = File::open(&Path::new("hello.ah")).read_to_str().unwrap();
+        let mut out = MemWriter::new();
+        hello(&mut MemReader::new(Vec::new()), &mut out);
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap(), run(text.as_slice(), ""));
+    }
+
+    #[cfg(target_os = "linux", target_arch = "x86_64")]
+    fn run_jit(source: &str, input: &str) -> String {
+        let (tx, rx) = channel();
+        {
+            let reader = MemReader::new(Vec::from_slice(input.as_bytes()));
+            let mut it = Interpreter::with_io(Source::from_str(source), box reader, box ChanWriter::new(tx));
+            aheui::jit::execute(&mut it);
+            assert_eq!(it.counter(), {
+                let mut it = Interpreter::with_io(Source::from_str(source), box MemReader::new(Vec::from_slice(input.as_bytes())), box MemWriter::new());
+                it.execute();
+                it.counter()
+            });
+        }
+        collect(rx)
+    }
+
+    #[test]
+    #[cfg(target_os = "linux", target_arch = "x86_64")]
+    pub fn test_jit() {
+        let text = File::open(&Path::new("hello.ah")).read_to_str().unwrap();
+        assert_eq!(run_jit(text.as_slice(), ""), run(text.as_slice(), ""));
+        assert_eq!(run_jit("방방다망희", "3\n4\n").as_slice(), "7");
+        assert_eq!(run_jit("밯밯망망희", "가A").as_slice(), "6544032");
+        assert_eq!(run_jit("상반발밞팡닷망망희", "").as_slice(), "97");
+        assert_eq!(run_jit("아멍희", "").as_slice(), "");
+
+        // The storages come back to the interpreter, the queue having grown
+        // past its first buffer.
+        let mut text = String::from_str("반받상박");
+        text.push_str("바".repeat(70).as_slice());
+        text.push_str("희");
+        let mut it = Interpreter::with_io(Source::from_str(text.as_slice()), box MemReader::new(Vec::new()), box MemWriter::new());
+        aheui::jit::execute(&mut it);
+        assert_eq!(it.storage_at(0).values(), vec!(2, 3));
+        assert_eq!(it.storage_at(21).values(), Vec::from_fn(71, |i| if i == 0 { 2 } else { 0 }));
+        assert_eq!(it.storage_index(), 21);
+    }
+
+    #[cfg(target_os = "linux", target_arch = "x86_64")]
+    fn elf_output(source: &str, input: &str) -> ProcessOutput {
+        let dir = TempDir::new("aheui").unwrap();
+        let path = dir.path().join("program");
+        aheui::elf::write_executable(&Source::from_str(source), &path).unwrap();
+        let mut process = Command::new(&path).spawn().unwrap();
+        {
+            let stdin = process.stdin.get_mut_ref();
+            stdin.write_str(input).unwrap();
+        }
+        drop(process.stdin.take());
+        process.wait_with_output().unwrap()
+    }
+
+    #[cfg(target_os = "linux", target_arch = "x86_64")]
+    fn run_elf(source: &str, input: &str) -> String {
+        let output = elf_output(source, input);
+        assert!(output.status.success());
+        String::from_utf8(output.output).unwrap()
+    }
+
+    #[test]
+    #[cfg(target_os = "linux", target_arch = "x86_64")]
+    pub fn test_elf() {
+        let text = File::open(&Path::new("hello.ah")).read_to_str().unwrap();
+        assert_eq!(run_elf(text.as_slice(), ""), run(text.as_slice(), ""));
+        assert_eq!(run_elf("방방다망희", "3\n4\n").as_slice(), "7");
+        assert_eq!(run_elf("방방타망희", "-3\n4\n").as_slice(), "-7");
+        assert_eq!(run_elf("밯밯맣맣희", "가A").as_slice(), "A가");
+        assert_eq!(run_elf("상반발밞팡닷망망희", "").as_slice(), "97");
+        assert_eq!(run_elf("아멍희", "").as_slice(), "");
+
+        // Pushing forever fills a stack, and a queue despite wrapping around.
+        for text in ["바", "상바"].iter() {
+            let output = elf_output(*text, "");
+            assert!(!output.status.success());
+            assert_eq!(String::from_utf8(output.error).unwrap().as_slice(), "aheui: storage overflow\n");
+        }
+        let output = elf_output("바밤타맣희", "");
+        assert!(!output.status.success());
+        assert_eq!(String::from_utf8(output.error).unwrap().as_slice(), "aheui: invalid character\n");
+    }
+
+    #[test]
+    pub fn test_debugger() {
+        let it = Interpreter::with_io(Source::from_str("반받다망희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        let mut debugger = Debugger::new(it);
+        let mut out = MemWriter::new();
+        assert!(debugger.command("break 망", &mut out).unwrap());
+        assert!(debugger.command("c", &mut out).unwrap());
+        assert_eq!(debugger.interpreter().counter(), (0, 3));
+        assert!(debugger.command("p", &mut out).unwrap());
+        assert!(debugger.command("s 2", &mut out).unwrap());
+        assert!(!debugger.command("q", &mut out).unwrap());
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+                   "breakpoint 0: 망\nbreakpoint 0\n(0, 3) 망 print-int\n* 0  stack [5]\nhalted at (0, 4) 희 halt\n");
+    }
+
+    fn is_two(len: uint) -> bool {
+        len == 2
+    }
+
+    #[test]
+    pub fn test_breakpoints() {
+        let mut it = Interpreter::with_io(Source::from_str("반받다망희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        assert_eq!(it.add_breakpoint(OperationBreakpoint(aheui::BinaryKind)), 0);
+        assert_eq!(it.add_breakpoint(DirectedCellBreakpoint((0, 4), Left)), 1);
+        assert_eq!(it.add_watchpoint(LengthWatchpoint(0, is_two)), 0);
+        assert_eq!(it.run(), Stopped(WatchpointHit(0)));
+        assert_eq!(it.counter(), (0, 2));
+        assert_eq!(it.run(), Stopped(BreakpointHit(0)));
+        assert_eq!(it.counter(), (0, 2));
+        assert_eq!(it.run(), Halted);
+        assert_eq!(it.counter(), (0, 4));
+
+        let mut it = Interpreter::with_io(Source::from_str("아상희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.add_breakpoint(CellBreakpoint((0, 1)));
+        it.add_watchpoint(StorageSelectionWatchpoint);
+        assert_eq!(it.run(), Stopped(BreakpointHit(0)));
+        assert_eq!(it.run(), Stopped(WatchpointHit(0)));
+        assert_eq!(it.counter(), (0, 2));
+        assert!(it.remove_breakpoint(0).is_some());
+        assert_eq!(it.run(), Halted);
+
+        let mut it = Interpreter::with_io(Source::from_str("아희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.add_breakpoint(CellBreakpoint((0, 0)));
+        assert_eq!(it.run(), Stopped(BreakpointHit(0)));
+        assert_eq!(it.counter(), (0, 0));
+        assert_eq!(it.run(), Halted);
+    }
+
+    fn trace(source: &str, format: aheui::trace::TraceFormat) -> String {
+        let (tx, rx) = channel();
+        {
+            let mut it = Interpreter::with_io(Source::from_str(source), box MemReader::new(Vec::new()), box MemWriter::new());
+            it.set_trace(box ChanWriter::new(tx), format);
+            it.execute();
+        }
+        collect(rx)
+    }
+
+    #[test]
+    pub fn test_trace() {
+        assert_eq!(trace("반받다망희", TextTrace).as_slice(),
+                   "1\t0,0\t반\tpush 2\tright\t-\t0\t2\n\
+                    2\t0,1\t받\tpush 3\tright\t-\t0\t3,2\n\
+                    3\t0,2\t다\tadd\tright\t-\t0\t5\n\
+                    4\t0,3\t망\tprint-int\tright\t-\t0\t\n\
+                    5\t0,4\t희\thalt\tright\t-\t0\t\n");
+        let json = trace("머반희", JsonTrace);
+        let lines: Vec<&str> = json.as_slice().lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(*lines.get(0),
+                   "{\"step\":1,\"row\":0,\"col\":0,\"syllable\":\"머\",\"op\":\"pop\",\"direction\":\"right\",\"underflow\":true,\"storage\":0,\"top\":[]}");
+        // ㅊ picking 0 reverses the movement without an underflow.
+        assert_eq!(trace("바추\n희희", TextTrace).as_slice(),
+                   "1\t0,0\t바\tpush 0\tright\t-\t0\t0\n\
+                    2\t0,1\t추\tbranch\tup\t-\t0\t\n\
+                    3\t1,1\t희\thalt\tup\t-\t0\t\n");
+        // A carriage return, as in a source with CRLF line ends.
+        let json = trace("아\r희", JsonTrace);
+        assert!(json.as_slice().lines().nth(1).unwrap().contains("\"syllable\":\"\\r\""));
+        assert_eq!(aheui::trace::quote("\"\\\x01").as_slice(), "\"\\\"\\\\\\u0001\"");
+    }
+
+    #[test]
+    pub fn test_step_back() {
+        let mut it = Interpreter::with_io(Source::from_str("반받다망희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.add_breakpoint(CellBreakpoint((0, 1)));
+        it.enable_history(2, 3);
+        it.execute();
+        assert!(it.step_back());
+        assert_eq!(it.counter(), (0, 3));
+        assert_eq!(it.storage().values(), vec!(5));
+        assert!(it.step_back());
+        assert_eq!(it.counter(), (0, 2));
+        assert_eq!(it.storage().values(), vec!(2, 3));
+        assert_eq!(it.run_back(), Some(0));
+        assert_eq!(it.storage().values(), vec!(2));
+        assert_eq!(it.run_back(), None);
+        assert_eq!(it.counter(), (0, 0));
+        assert!(it.storage().values().is_empty());
+
+        let (tx, rx) = channel();
+        {
+            let reader = MemReader::new(Vec::from_slice("42\n".as_bytes()));
+            let mut it = Interpreter::with_io(Source::from_str("방망희"), box reader, box ChanWriter::new(tx));
+            it.enable_history(10, 1);
+            it.execute();
+            assert!(it.step_back());
+            assert!(it.step_back());
+            assert!(!it.step_back());
+            // The input is exhausted; 42 is read again from the history.
+            it.execute();
+        }
+        assert_eq!(collect(rx).as_slice(), "4242");
+    }
+
+    #[test]
+    pub fn test_snapshot() {
+        let mut it = Interpreter::with_io(Source::from_str("반받다망희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.add_breakpoint(CellBreakpoint((0, 3)));
+        assert_eq!(it.run(), Stopped(BreakpointHit(0)));
+        let snapshot = it.snapshot();
+        let text = snapshot.to_str();
+        assert!(text.as_slice().starts_with("aheui-snapshot 1\n"));
+        assert!(text.as_slice().contains("\ncounter 0 3\ndirection right\n"));
+        assert!(text.as_slice().ends_with("\nvalues 0 5\n"));
+        let parsed = Snapshot::from_str(text.as_slice()).unwrap();
+        assert_eq!(parsed, snapshot);
+
+        let (tx, rx) = channel();
+        {
+            let mut it = Interpreter::with_io(Source::from_str("반받다망희"), box MemReader::new(Vec::new()), box ChanWriter::new(tx));
+            assert!(it.restore(&parsed).is_ok());
+            assert_eq!(it.counter(), (0, 3));
+            it.execute();
+        }
+        assert_eq!(collect(rx).as_slice(), "5");
+
+        let mut other = Interpreter::with_io(Source::from_str("반받타망희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        assert!(other.restore(&parsed).is_err());
+        assert!(Snapshot::from_str("aheui-snapshot 1\ncounter 0\n").is_err());
+        let mut outside = parsed.clone();
+        outside.counter = (5, 0);
+        assert!(Interpreter::new(Source::from_str("반받다망희")).restore(&outside).is_err());
+        outside.counter = (0, 6);
+        assert!(Interpreter::new(Source::from_str("반받다망희")).restore(&outside).is_ok());
+    }
+
+    #[test]
+    pub fn test_visualize() {
+        let mut visualizer = Visualizer::new(Source::from_str("반받다망희"), box MemReader::new(Vec::new()), 3, 3, 0);
+        assert!(!visualizer.step());
+        assert!(!visualizer.step());
+        let mut out = MemWriter::new();
+        visualizer.render(&mut out, true).unwrap();
+        let frame = String::from_utf8(out.unwrap()).unwrap();
+        assert!(frame.as_slice().contains("step 2  (0, 2) →  paused  다 add\n"));
+        assert!(frame.as_slice().contains("받\x1b[7m다\x1b[0m망  storage 0  stack\n        3\n        2\n"));
+    }
+
+    #[test]
+    pub fn test_profile() {
+        let mut it = Interpreter::with_io(Source::from_str("머반희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.enable_profile();
+        it.execute();
+        let profile = it.profile().unwrap();
+        assert_eq!(profile.steps(), 3);
+        assert_eq!(profile.count_directed((0, 0), aheui::Down), 1);
+        assert_eq!(profile.underflows((0, 0)), 1);
+        assert_eq!(profile.count_directed((0, 1), aheui::Right), 1);
+        let mut out = MemWriter::new();
+        profile.write_csv(it.source(), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+                   "row,col,syllable,count,down,up,right,left,underflows\n\
+                    0,0,머,1,1,0,0,0,1\n\
+                    0,1,반,1,0,0,1,0,0\n\
+                    0,2,희,1,0,0,1,0,0\n");
+    }
+
+    #[test]
+    pub fn test_profile_zero_branch() {
+        let mut it = Interpreter::with_io(Source::from_str("바추\n희희"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.enable_profile();
+        it.execute();
+        let profile = it.profile().unwrap();
+        assert_eq!(profile.count((0, 1)), 1);
+        assert_eq!(profile.underflows((0, 1)), 0);
+    }
+
+    fn cover(source: &str, input: &str) -> aheui::coverage::Coverage {
+        let reader = MemReader::new(Vec::from_slice(input.as_bytes()));
+        let mut it = Interpreter::with_io(Source::from_str(source), box reader, box MemWriter::new());
+        it.enable_coverage();
+        it.execute();
+        it.coverage().unwrap().clone()
+    }
+
+    #[test]
+    pub fn test_coverage() {
+        let program = "붕희\n아추\n아희";
+        let source = Source::from_str(program);
+        let mut coverage = cover(program, "1\n");
+        let summary = coverage.summary(&source);
+        assert_eq!(summary.cells, (4, 6));
+        assert_eq!(summary.branch_outcomes, (1, 2));
+        coverage.merge(&cover(program, "0\n"));
+        let mut out = MemWriter::new();
+        coverage.write_report(&source, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap().as_slice(),
+                   "   0 붕희\n   1 아추\n   2 아희\n     ^^\n\
+                    cells: 5/6 (83.3%)\nbranch outcomes: 2/2 (100.0%)\nreflection points: none\n");
+    }
+
+    struct Events {
+        tx: Sender<String>,
+    }
+
+    impl InterpreterObserver for Events {
+        fn on_step(&mut self, (row, col): (int, int), instruction: &Instruction) {
+            self.tx.send(format!("step {} {} {}", row, col, instruction.char().unwrap()));
+        }
+        fn on_push(&mut self, storage: uint, value: int) {
+            self.tx.send(format!("push {} {}", storage, value));
+        }
+        fn on_pop(&mut self, storage: uint, value: int) {
+            self.tx.send(format!("pop {} {}", storage, value));
+        }
+        fn on_output(&mut self, text: &str) {
+            self.tx.send(format!("output {}", text));
+        }
+        fn on_reflect(&mut self, (row, col): (int, int), _: &Instruction) {
+            self.tx.send(format!("reflect {} {}", row, col));
+        }
+        fn on_halt(&mut self, (row, col): (int, int)) {
+            self.tx.send(format!("halt {} {}", row, col));
+        }
+    }
+
+    #[test]
+    pub fn test_observer() {
+        let (tx, rx) = channel();
+        {
+            let mut it = Interpreter::with_io(Source::from_str("머반망희"), box MemReader::new(Vec::new()), box MemWriter::new());
+            assert_eq!(it.add_observer(box Events { tx: tx }), 0);
+            it.execute();
+        }
+        let events: Vec<String> = rx.iter().collect();
+        assert_eq!(events.iter().map(|e| e.as_slice()).collect::<Vec<&str>>(),
+                   vec!("step 0 0 머", "reflect 0 0", "step 0 1 반", "push 0 2",
+                        "step 0 2 망", "pop 0 2", "output 2", "step 0 3 희", "halt 0 3"));
+    }
+
+    #[test]
+    pub fn test_dap() {
+        let dir = TempDir::new("aheui").unwrap();
+        let path = dir.path().join("sum.ah");
+        File::create(&path).write_str("반받다망희").unwrap();
+        let path = path.as_str().unwrap();
+
+        let (requests, rx) = channel();
+        requests.send(String::from_str("{\"seq\":1,\"type\":\"request\",\"command\":\"initialize\"}"));
+        requests.send(format!("{{\"seq\":2,\"type\":\"request\",\"command\":\"launch\",\
+                               \"arguments\":{{\"program\":\"{}\",\"stopOnEntry\":true}}}}", path));
+        requests.send(format!("{{\"seq\":3,\"type\":\"request\",\"command\":\"setBreakpoints\",\
+                               \"arguments\":{{\"source\":{{\"path\":\"{}\"}},\"breakpoints\":[{{\"line\":1}}]}}}}", path));
+        requests.send(String::from_str("{\"seq\":4,\"type\":\"request\",\"command\":\"configurationDone\"}"));
+        requests.send(String::from_str("{\"seq\":5,\"type\":\"request\",\"command\":\"next\",\"arguments\":{\"threadId\":1}}"));
+        requests.send(String::from_str("{\"seq\":6,\"type\":\"request\",\"command\":\"stackTrace\",\"arguments\":{\"threadId\":1}}"));
+        requests.send(String::from_str("{\"seq\":7,\"type\":\"request\",\"command\":\"variables\",\"arguments\":{\"variablesReference\":1}}"));
+        requests.send(String::from_str("{\"seq\":8,\"type\":\"request\",\"command\":\"disconnect\"}"));
+        drop(requests);
+
+        let (tx, output) = channel();
+        {
+            let mut server = Server::new(box ChanWriter::new(tx));
+            server.serve(rx).unwrap();
+        }
+        let messages = collect(output);
+        let messages = messages.as_slice();
+        assert!(messages.starts_with("Content-Length: "));
+        assert!(messages.contains("\"breakpoints\":[{\"id\":1,\"verified\":true,\"line\":1,\"column\":1}]"));
+        assert!(messages.contains("\"event\":\"stopped\",\"body\":{\"reason\":\"entry\",\"threadId\":1}"));
+        assert!(messages.contains("\"event\":\"stopped\",\"body\":{\"reason\":\"step\",\"threadId\":1}"));
+        assert!(messages.contains("\"name\":\"받 push 3\",\"line\":1,\"column\":2"));
+        assert!(messages.contains("{\"name\":\"0\",\"value\":\"[2]\",\"type\":\"stack\",\"variablesReference\":0}"));
+        assert!(messages.contains("\"request_seq\":8,\"success\":true,\"command\":\"disconnect\""));
+    }
+
+    fn dap_session(requests: &[String]) -> String {
+        let (tx, rx) = channel();
+        for request in requests.iter() {
+            tx.send(request.clone());
+        }
+        drop(tx);
+        let (tx, output) = channel();
+        {
+            let mut server = Server::new(box ChanWriter::new(tx));
+            server.serve(rx).unwrap();
+        }
+        collect(output)
+    }
+
+    #[test]
+    pub fn test_dap_halt() {
+        let dir = TempDir::new("aheui").unwrap();
+        let reading = dir.path().join("read.ah");
+        File::create(&reading).write_str("방망희").unwrap();
+        let printing = dir.path().join("print.ah");
+        File::create(&printing).write_str("밤망희").unwrap();
+
+        let messages = dap_session([
+            format!("{{\"seq\":1,\"type\":\"request\",\"command\":\"launch\",\
+                     \"arguments\":{{\"program\":\"{}\"}}}}", reading.as_str().unwrap()),
+            String::from_str("{\"seq\":2,\"type\":\"request\",\"command\":\"disconnect\"}")]);
+        assert!(messages.as_slice().contains("\"request_seq\":1,\"success\":false,\"command\":\"launch\",\
+                                              \"message\":\"the program reads input, but no input file was given\""));
+
+        let messages = dap_session([
+            format!("{{\"seq\":1,\"type\":\"request\",\"command\":\"launch\",\
+                     \"arguments\":{{\"program\":\"{}\"}}}}", printing.as_str().unwrap()),
+            String::from_str("{\"seq\":2,\"type\":\"request\",\"command\":\"configurationDone\"}"),
+            String::from_str("{\"seq\":3,\"type\":\"request\",\"command\":\"threads\"}"),
+            String::from_str("{\"seq\":4,\"type\":\"request\",\"command\":\"continue\",\"arguments\":{\"threadId\":1}}"),
+            String::from_str("{\"seq\":5,\"type\":\"request\",\"command\":\"next\",\"arguments\":{\"threadId\":1}}"),
+            String::from_str("{\"seq\":6,\"type\":\"request\",\"command\":\"disconnect\"}")]);
+        let messages = messages.as_slice();
+        assert!(messages.contains("\"output\":\"4\""));
+        assert_eq!(messages.split_str("\"event\":\"terminated\"").count(), 2);
+        assert!(messages.contains("\"request_seq\":6,\"success\":true,\"command\":\"disconnect\""));
+        assert!(messages.contains("\"exitCode\":0"));
+
+        // Stops at the division before dividing by zero. The unverified
+        // breakpoint takes no id.
+        let failing = dir.path().join("fail.ah");
+        File::create(&failing).write_str("바바나희").unwrap();
+        let failing = failing.as_str().unwrap();
+        let messages = dap_session([
+            format!("{{\"seq\":1,\"type\":\"request\",\"command\":\"launch\",\
+                     \"arguments\":{{\"program\":\"{}\"}}}}", failing),
+            format!("{{\"seq\":2,\"type\":\"request\",\"command\":\"setBreakpoints\",\
+                     \"arguments\":{{\"source\":{{\"path\":\"{}\"}},\
+                     \"breakpoints\":[{{\"line\":0}},{{\"line\":1,\"column\":3}}]}}}}", failing),
+            String::from_str("{\"seq\":3,\"type\":\"request\",\"command\":\"configurationDone\"}"),
+            String::from_str("{\"seq\":4,\"type\":\"request\",\"command\":\"threads\"}"),
+            String::from_str("{\"seq\":5,\"type\":\"request\",\"command\":\"continue\",\"arguments\":{\"threadId\":1}}"),
+            String::from_str("{\"seq\":6,\"type\":\"request\",\"command\":\"threads\"}"),
+            String::from_str("{\"seq\":7,\"type\":\"request\",\"command\":\"disconnect\"}")]);
+        let messages = messages.as_slice();
+        assert!(messages.contains("\"breakpoints\":[{\"verified\":false,\"line\":0},\
+                                   {\"id\":1,\"verified\":true,\"line\":1,\"column\":3}]"));
+        assert!(messages.contains("\"reason\":\"breakpoint\",\"threadId\":1,\"hitBreakpointIds\":[1]"));
+        assert!(messages.contains("\"category\":\"stderr\""));
+        assert!(messages.contains("\"exitCode\":1"));
+    }
+
+    fn warnings(source: &str) -> Vec<String> {
+        lint(&Source::from_str(source), false).iter().map(|warning| {
+            let mut out = MemWriter::new();
+            warning.write("p", &mut out).unwrap();
+            String::from_utf8(out.unwrap()).unwrap()
+        }).collect()
+    }
+
+    #[test]
+    pub fn test_reachability() {
+        let source = Source::from_str("아희\n바반\n차우");
+        let reachability = Reachability::from_source(&source);
+        assert!(reachability.halts);
+        assert_eq!(reachability.unreachable(&source), vec!((1, 0), (1, 1), (2, 0), (2, 1)));
+        assert_eq!(warnings("아희\n바\n차우"),
+                   vec!(String::from_str("p:2:1: warning: 바 can never be executed\n"),
+                        String::from_str("p:3:1: warning: 2 cells from here can never be executed\n")));
+        assert_eq!(warnings("아아"), vec!(String::from_str("p: warning: no halt (ㅎ) can be reached, so the program never ends\n")));
+    }
+
+    #[test]
+    pub fn test_depth() {
+        assert_eq!(DepthAnalysis::from_source(&Source::from_str("다희")).underflow((0, 0)), Some(Always));
+        assert_eq!(DepthAnalysis::from_source(&Source::from_str("바바다희")).underflow((0, 2)), Some(Never));
+        let looping = DepthAnalysis::from_source(&Source::from_str("바다"));
+        assert_eq!(looping.underflow((0, 1)), Some(Sometimes));
+        assert_eq!(looping.underflow((0, 0)), None);
+        assert_eq!(warnings("다희"), vec!(String::from_str("p:1:1: warning: 다 (add) always underflows its storage and reflects\n")));
+        let warned: Vec<String> = lint(&Source::from_str("바다"), true).iter().map(|warning| warning.message.clone()).collect();
+        assert!(warned.contains(&String::from_str("다 (add) may underflow its storage and reflect")));
+        assert!(warnings("바다").iter().all(|warning| !warning.as_slice().contains("may underflow")));
+    }
+
+    #[test]
+    pub fn test_cfg() {
+        let flow = ControlFlow::from_source(&Source::from_str("바추\n희희"));
+        let mut out = MemWriter::new();
+        flow.write_dot(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap(), String::from_str("digraph aheui {
+    node [shape=box, fontname=\"monospace\"];
+    start [shape=point];
+    b0 [label=\"(0,0)..(0,1)\\n바추\"];
+    b1 [label=\"(1,1)\\n희\", peripheries=2];
+    b2 [label=\"(1,1)\\n희\", peripheries=2];
+    start -> b0;
+    b0 -> b1 [label=\"down, nonzero\"];
+    b0 -> b2 [label=\"up, zero\"];
+}
+"));
+    }
+
+    #[test]
+    pub fn test_symbolic() {
+        let constraints = [Constraint { expr: Linear { constant: -1, terms: vec!((0, 2), (1, -1)) }, relation: EqualZero },
+                           Constraint { expr: Linear { constant: -5, terms: vec!((1, 1)) }, relation: NonNegative }];
+        assert_eq!(symbolic::solve(&constraints, &[IntegerInput, IntegerInput]), Sat(vec!(3, 5)));
+        let contradiction = [Constraint { expr: Linear::input(0), relation: NonNegative },
+                             Constraint { expr: Linear::input(0), relation: Negative }];
+        assert_eq!(symbolic::solve(&contradiction, &[IntegerInput]), Unsat);
+        // 2x + 2y = 1 has no solution, but the solver cannot tell.
+        let parity = [Constraint { expr: Linear { constant: -1, terms: vec!((0, 2), (1, 2)) }, relation: EqualZero }];
+        assert_eq!(symbolic::solve(&parity, &[IntegerInput, IntegerInput]), Unknown);
+
+        // Reads x and branches on x - 3.
+        let exploration = symbolic::explore(&Source::from_str("방받타추\n희희희희"), 10, 100);
+        assert!(exploration.complete);
+        let zero = exploration.witness(BranchTarget((0, 3), false)).unwrap();
+        assert!(zero.exact);
+        assert_eq!(zero.input_text(), String::from_str("3\n"));
+        assert_eq!(exploration.witness(BranchTarget((0, 3), true)).unwrap().inputs, vec!(0));
+        assert_eq!(exploration.witness(HaltTarget((1, 3))).unwrap().inputs, vec!(0));
+        assert!(exploration.witness(HaltTarget((1, 0))).is_none());
+
+        // Divides 3 by x, which must not be zero, then by a product.
+        let exploration = symbolic::explore(&Source::from_str("받방나망희"), 10, 100);
+        let halt = exploration.witness(HaltTarget((0, 4))).unwrap();
+        assert!(halt.exact);
+        assert_eq!(halt.inputs, vec!(1));
+        let exploration = symbolic::explore(&Source::from_str("받방방따나망희"), 10, 100);
+        assert!(!exploration.witness(HaltTarget((0, 6))).unwrap().exact);
+    }
+
+    #[test]
+    pub fn test_usage() {
+        let source = Source::from_str("박쌍박다희");
+        let usage = Usage::from_source(&source);
+        assert_eq!(usage.used(), vec!(0, 21));
+        let mut out = MemWriter::new();
+        usage.write_report(&source, &mut out).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap(), String::from_str("storage 0 (stack)
+    0,0 박 push
+    0,1 쌍 move to 21
+storage 21 ㅇ (queue)
+    0,1 쌍 move from 0
+    0,2 박 push
+    0,3 다 pick+push
+flows
+    0 -> 21: 0,1
+"));
+    }
+
+    #[test]
+    pub fn test_loop_detection() {
+        // Pushes and pops forever.
+        let mut it = Interpreter::with_io(Source::from_str("바마"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.enable_loop_detection();
+        match it.run() {
+            Stopped(InfiniteLoop(found)) => {
+                let mut cells = found.cells.clone();
+                cells.sort();
+                assert_eq!(cells, vec!((0, 0), (0, 1)));
+            }
+            result => fail!("unexpected {}", result),
+        }
+        assert_eq!(Loop { length: 3, cells: vec!((0, 0), (0, 1)) }.describe(),
+                   String::from_str("every 3 steps through 0,0 0,1"));
+
+        // The lap taken to find the cells is not recorded in the history.
+        let mut it = Interpreter::with_io(Source::from_str("바우\n오머"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.enable_loop_detection();
+        it.enable_profile();
+        it.enable_history(1000, 10);
+        match it.run() {
+            Stopped(InfiniteLoop(found)) => { assert_eq!(found.length, 4); }
+            result => fail!("unexpected {}", result),
+        }
+        let mut undone = 0;
+        while it.step_back() {
+            undone += 1;
+        }
+        assert_eq!(undone, it.profile().unwrap().steps());
+
+        // Grows its storage, so no state recurs.
+        let mut it = Interpreter::with_io(Source::from_str("바"), box MemReader::new(Vec::new()), box MemWriter::new());
+        it.enable_loop_detection();
+        assert_eq!(it.run_steps(1000), None);
+
+        // Comes back to the same state on every turn, but reads input in
+        // between and halts on 0.
+        let mut it = Interpreter::with_io(Source::from_str("방추\n오어\n아희"),
+                                          box MemReader::new(Vec::from_slice(b"1\n1\n0\n")), box MemWriter::new());
+        it.enable_loop_detection();
+        assert_eq!(it.run(), Halted);
+    }
+
+    #[test]
+    pub fn test_suspicious() {
+        assert_eq!(warnings("바바나희"), vec!(String::from_str("p:1:3: warning: 나 (div) divides by 0 and fails\n")));
+        assert_eq!(warnings("바반타맣희"),
+                   vec!(String::from_str("p:1:4: warning: 맣 prints -2, which is not a character, and fails\n")));
+        assert_eq!(warnings("샇희"),
+                   vec!(String::from_str("p:1:1: warning: 샇 uses the ㅎ storage, which is reserved for extensions\n")));
+        assert_eq!(warnings("으\n희"),
+                   vec!(String::from_str("p:1:1: warning: 으 turns the counter back on the way from the start\n")));
+        assert_eq!(warnings("아ㅎ\u00a0희"),
+                   vec!(String::from_str("p:1:2: warning: ㅎ is a compatibility jamo, not a syllable, and does nothing\n"),
+                        String::from_str("p:1:3: warning: U+00A0 (no-break space) takes a column, shifting the cells after it\n")));
+    }
+
+    #[test]
+    pub fn test_strip() {
+        let original = "아희다  \n바바\n\n";
+        let stripped = strip::strip(&Source::from_str(original));
+        assert_eq!(stripped.text, String::from_str("아희"));
+        assert_eq!((stripped.blanked, stripped.trimmed_cells, stripped.trimmed_rows), (3, 5, 3));
+        assert_eq!(strip::verify(original, stripped.text.as_slice(), &[], 100), Ok(()));
+        assert!(strip::verify("밯맣희", "희", b"a", 100).is_err());
+        // Reading past the input and dividing by zero are outcomes too.
+        assert_eq!(strip::verify("방망희", "방망희", &[], 100), Ok(()));
+        assert_eq!(strip::verify("바바나망희", "희", &[], 100),
+                   Err(String::from_str("the original program fails within 100 steps, but the stripped one halts")));
+    }
+
+    #[test]
+    pub fn test_stats() {
+        let mut out = MemWriter::new();
+        Stats::from_source(&Source::from_str("바다샹\n아x")).write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap(), String::from_str("size: 2 rows, 3 columns
+cells: 4 hangul, 1 other
+bytes: 14 UTF-8, 12 UTF-16
+operations:
+    add              1
+    nop              1
+    push             1
+    select           1
+movements:
+    right 1          3
+    right 2          1
+storages: 2 (0, 21)
+"));
+        let mut out = MemWriter::new();
+        Stats::from_source(&Source::from_str("밯망희")).write(&mut out).unwrap();
+        assert!(String::from_utf8(out.unwrap()).unwrap().as_slice().ends_with("storages: 1 (0)\n"));
+    }
+
+    #[test]
+    pub fn test_disasm() {
+        let mut out = MemWriter::new();
+        Listing::from_source(&Source::from_str("밤추\n희희")).write(&mut out).unwrap();
+        assert_eq!(String::from_utf8(out.unwrap()).unwrap(), String::from_str("L0:
+    (0,0) 밤 push 4 →
+    (0,1) 추 branch ↓
+    nonzero: jump L1
+    zero: jump L2 (wrap)
+L1:
+    (1,1) 희 halt
+L2:
+    (1,1) 희 halt
+"));
+    }
 }
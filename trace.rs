@@ -0,0 +1,114 @@
+//! Execution traces for comparing runs with other implementations.
+//!
+//! One record is written per executed syllable; the steps the interpreter
+//! spends on its virtual walls are left out. A JSON record looks like
+//!
+//! ```text
+//! {"step":1,"row":0,"col":0,"syllable":"밤","op":"push 4","direction":"right","underflow":false,"storage":0,"top":[4]}
+//! ```
+//!
+//! and a text record holds the same fields separated by tabs:
+//!
+//! ```text
+//! 1	0,0	밤	push 4	right	-	0	4
+//! ```
+//!
+//! `direction` is the direction after the step, `underflow` whether it was
+//! reversed because the storage lacked values, and `top` lists up to
+//! `TOP_VALUES` values of the selected storage, the next one to be picked
+//! first. A run which halts ends with a record whose `op` is `halt`; the
+//! direction is left as it was.
+
+use std::io::IoResult;
+
+use super::{Interpreter, Instruction, Storage};
+
+pub static TOP_VALUES: uint = 3;
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum TraceFormat {
+    JsonTrace,
+    TextTrace,
+}
+
+pub struct Tracer {
+    out: Box<Writer>,
+    format: TraceFormat,
+    steps: u64,
+}
+
+impl Tracer {
+    pub fn new(out: Box<Writer>, format: TraceFormat) -> Tracer {
+        Tracer { out: out, format: format, steps: 0 }
+    }
+
+    /// Records the step which executed `instruction` at `position`.
+    pub fn record(&mut self, interpreter: &Interpreter, position: (int, int), instruction: &Instruction) {
+        let syllable = match instruction.char() {
+            Some(c) => c,
+            None => { return; }
+        };
+        self.steps += 1;
+        let index = interpreter.storage_index();
+        let top = top_values(interpreter.storage_at(index), index);
+        let direction = interpreter.direction().name();
+        let result = match self.format {
+            JsonTrace => self.write_json(position, syllable, instruction, direction, interpreter.underflowed(), index, top.as_slice()),
+            TextTrace => self.write_text(position, syllable, instruction, direction, interpreter.underflowed(), index, top.as_slice()),
+        };
+        match result {
+            Ok(()) => { }
+            Err(e) => fail!("aheui: cannot write trace: {}", e),
+        }
+    }
+
+    fn write_json(&mut self, (row, col): (int, int), syllable: char, instruction: &Instruction, direction: &str,
+                  underflow: bool, index: uint, top: &[int]) -> IoResult<()> {
+        try!(write!(self.out, "{{\"step\":{},\"row\":{},\"col\":{},\"syllable\":", self.steps, row, col));
+        try!(write!(self.out, "{}", quote(String::from_char(1, syllable).as_slice())));
+        try!(write!(self.out, ",\"op\":\"{}\",\"direction\":\"{}\",\"underflow\":{},\"storage\":{},\"top\":[",
+                    instruction.mnemonic(), direction, underflow, index));
+        for (i, v) in top.iter().enumerate() {
+            try!(write!(self.out, "{}{}", if i > 0 { "," } else { "" }, v));
+        }
+        writeln!(self.out, "]}}")
+    }
+
+    fn write_text(&mut self, (row, col): (int, int), syllable: char, instruction: &Instruction, direction: &str,
+                  underflow: bool, index: uint, top: &[int]) -> IoResult<()> {
+        try!(write!(self.out, "{}\t{},{}\t{}\t{}\t{}\t{}\t{}\t", self.steps, row, col, syllable,
+                    instruction.mnemonic(), direction, if underflow { "underflow" } else { "-" }, index));
+        for (i, v) in top.iter().enumerate() {
+            try!(write!(self.out, "{}{}", if i > 0 { "," } else { "" }, v));
+        }
+        writeln!(self.out, "")
+    }
+}
+
+/// Values of a storage in the order `pick` would return them.
+fn top_values(storage: &Storage, index: uint) -> Vec<int> {
+    let values = storage.values();
+    if index == 21 || index == 27 {
+        values.iter().take(TOP_VALUES).map(|v| *v).collect()
+    } else {
+        values.iter().rev().take(TOP_VALUES).map(|v| *v).collect()
+    }
+}
+
+/// JSON string literal for `s`.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::from_str("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(format!("\\u{:04x}", c as u32).as_slice()),
+            c => quoted.push_char(c),
+        }
+    }
+    quoted.push_str("\"");
+    quoted
+}
@@ -0,0 +1,170 @@
+//! Translates a `Source` into a standalone Rust function.
+//!
+//! Every reachable state of the program becomes an arm of a `match` inside a
+//! `loop`, so the generated code does no decoding or movement at run time.
+//! The function takes its input and output explicitly:
+//!
+//! ```ignore
+//! pub fn name<R: Buffer, W: Writer>(input: &mut R, output: &mut W)
+//! ```
+
+use std::io::{IoResult, MemWriter, File};
+
+use super::{Source, Instruction};
+use super::{NoOperation, PushConstantOperation, PushDuplicationOperation, PushIntegerInputOperation,
+            PushCharInputOperation, BinaryOperation, PopOperation, PrintIntegerOperation,
+            PrintCharOperation, SwapOperation, ChangeStorageOperation, MoveToStorageOperation,
+            CompareOperation, BranchOperation, HaltOperation};
+use graph::{Graph, Edge, Goto, Halt, Escape};
+
+static PRELUDE: &'static str = "    use std::collections::{Deque, RingBuf};
+
+    enum Storage {
+        Stack(Vec<int>),
+        Queue(RingBuf<int>),
+    }
+
+    impl Storage {
+        fn len(&self) -> uint {
+            match *self { Stack(ref s) => s.len(), Queue(ref q) => q.len() }
+        }
+        fn put(&mut self, v: int) {
+            match *self { Stack(ref mut s) => s.push(v), Queue(ref mut q) => q.push_back(v) }
+        }
+        fn rput(&mut self, v: int) {
+            match *self { Stack(ref mut s) => s.push(v), Queue(ref mut q) => q.push_front(v) }
+        }
+        fn pick(&mut self) -> Option<int> {
+            match *self { Stack(ref mut s) => s.pop(), Queue(ref mut q) => q.pop_front() }
+        }
+        fn peek(&self) -> Option<int> {
+            match *self { Stack(ref s) => s.last().map(|v| *v), Queue(ref q) => q.front().map(|v| *v) }
+        }
+    }
+
+    let mut storages: Vec<Storage> = Vec::from_fn(28, |i| {
+        if i == 21 || i == 27 { Queue(RingBuf::new()) } else { Stack(Vec::new()) }
+    });
+    let mut current: uint = 0;
+    let mut state: uint = 0;
+";
+
+/// Returns the Rust source of a function `name` running `source`.
+pub fn transpile(source: &Source, name: &str) -> String {
+    let mut w = MemWriter::new();
+    let _ = write_rust(source, name, &mut w);
+    String::from_utf8(w.unwrap()).unwrap()
+}
+
+/// Transpiles the program at `src` into a file at `dst`. Meant to be called
+/// from a build step, with `dst` pulled in by `include!`.
+pub fn transpile_file(src: &Path, dst: &Path, name: &str) -> IoResult<()> {
+    let text = try!(try!(File::open(src)).read_to_str());
+    let source = Source::from_str(text.as_slice());
+    let mut file = try!(File::create(dst));
+    write_rust(&source, name, &mut file)
+}
+
+pub fn write_rust(source: &Source, name: &str, w: &mut Writer) -> IoResult<()> {
+    let graph = Graph::from_source(source);
+    let escape = graph.len();
+
+    try!(writeln!(w, "#[allow(unused_mut, unused_variable, dead_code, unreachable_code)]"));
+    try!(writeln!(w, "pub fn {}<R: Buffer, W: Writer>(input: &mut R, output: &mut W) {{", name));
+    try!(w.write_str(PRELUDE));
+    try!(writeln!(w, "    loop {{"));
+    try!(writeln!(w, "        state = match state {{"));
+    for (idx, node) in graph.nodes.iter().enumerate() {
+        let (row, col) = node.state.position();
+        match node.instruction.char() {
+            Some(c) if !c.is_control() => {
+                try!(writeln!(w, "            {} => {{ // ({}, {}) {} {}", idx, row, col, c, node.instruction.operation.name()));
+            }
+            _ => {
+                try!(writeln!(w, "            {} => {{ // ({}, {})", idx, row, col));
+            }
+        }
+        try!(write_operation(w, &node.instruction));
+        let next = target(node.next, escape);
+        if node.next == Halt {
+            try!(writeln!(w, "                return;"));
+        } else {
+            match node.reflected {
+                Some(reflected) => {
+                    try!(writeln!(w, "                if reflect {{ {} }} else {{ {} }}", target(reflected, escape), next));
+                }
+                None => {
+                    try!(writeln!(w, "                {}", next));
+                }
+            }
+        }
+        try!(writeln!(w, "            }}"));
+    }
+    try!(writeln!(w, "            _ => fail!(\"aheui: the counter left the map\"),"));
+    try!(writeln!(w, "        }}"));
+    try!(writeln!(w, "    }}"));
+    try!(writeln!(w, "}}"));
+    Ok(())
+}
+
+fn target(edge: Edge, escape: uint) -> uint {
+    match edge {
+        Goto(idx) => idx,
+        Halt | Escape => escape,
+    }
+}
+
+fn write_operation(w: &mut Writer, instruction: &Instruction) -> IoResult<()> {
+    let code = match instruction.operation {
+        NoOperation | HaltOperation => {
+            return Ok(());
+        }
+        PushConstantOperation(v) => {
+            format!("storages.get_mut(current).put({});", v)
+        }
+        PushDuplicationOperation => {
+            String::from_str("let reflect = { let s = storages.get_mut(current); match s.peek() { Some(v) => { s.put(v); false } None => true } };")
+        }
+        PushIntegerInputOperation => {
+            String::from_str("let line = input.read_line().unwrap(); let v: int = from_str(line.as_slice().trim()).unwrap(); storages.get_mut(current).put(v);")
+        }
+        PushCharInputOperation => {
+            String::from_str("let c = input.read_char().unwrap(); storages.get_mut(current).put(c as int);")
+        }
+        BinaryOperation(_) => {
+            let operator = match instruction.binary_operator().unwrap() {
+                "add" => "+",
+                "mul" => "*",
+                "sub" => "-",
+                "div" => "/",
+                _ => "%",
+            };
+            format!("let reflect = {{ let s = storages.get_mut(current); if s.len() >= 2 {{ let v1 = s.pick().unwrap(); let v2 = s.pick().unwrap(); s.put(v2 {} v1); false }} else {{ true }} }};", operator)
+        }
+        PopOperation => {
+            String::from_str("let reflect = storages.get_mut(current).pick().is_none();")
+        }
+        PrintIntegerOperation => {
+            String::from_str("let v = storages.get_mut(current).pick(); let reflect = match v { Some(v) => { let _ = write!(output, \"{}\", v); false } None => true };")
+        }
+        PrintCharOperation => {
+            String::from_str("let v = storages.get_mut(current).pick(); let reflect = match v { Some(v) => { let _ = output.write_char(::std::char::from_u32(v as u32).unwrap()); false } None => true };")
+        }
+        SwapOperation => {
+            String::from_str("let reflect = { let s = storages.get_mut(current); if s.len() >= 2 { let v1 = s.pick().unwrap(); let v2 = s.pick().unwrap(); s.rput(v1); s.rput(v2); false } else { true } };")
+        }
+        ChangeStorageOperation(index) => {
+            format!("current = {};", index)
+        }
+        MoveToStorageOperation(index) => {
+            format!("let v = storages.get_mut(current).pick(); let reflect = match v {{ Some(v) => {{ current = {}; storages.get_mut(current).put(v); false }} None => true }};", index)
+        }
+        CompareOperation => {
+            String::from_str("let reflect = { let s = storages.get_mut(current); if s.len() >= 2 { let v1 = s.pick().unwrap(); let v2 = s.pick().unwrap(); s.put(if v2 >= v1 { 1 } else { 0 }); false } else { true } };")
+        }
+        BranchOperation => {
+            String::from_str("let v = storages.get_mut(current).pick(); let reflect = match v { Some(0) | None => true, Some(_) => false };")
+        }
+    };
+    writeln!(w, "                {}", code)
+}
@@ -0,0 +1,205 @@
+//! Which of the 28 storages a program uses, where, and how values move
+//! between them.
+//!
+//! The storage selected at each state of the `Graph` is found by following
+//! both edges of every operation from storage 0, so a site is reported when
+//! some path can select its storage there, whatever the values would be.
+
+use std::collections::HashSet;
+use std::io::IoResult;
+
+use super::{Source, storage_names};
+use super::{PushConstantOperation, PushIntegerInputOperation, PushCharInputOperation, PopOperation};
+use super::{PrintIntegerOperation, PrintCharOperation, BranchOperation, BinaryOperation, CompareOperation};
+use super::{PushDuplicationOperation, SwapOperation, ChangeStorageOperation, MoveToStorageOperation};
+use graph::{Graph, Goto};
+
+#[deriving(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub enum Access {
+    /// Selected by `ChangeStorageOperation`.
+    Select,
+    Push,
+    Pick,
+    /// Picks values and puts the result back, as arithmetic does.
+    PickPush,
+    /// A value is moved to the storage given.
+    MoveOut(uint),
+    /// A value is moved in from the storage given.
+    MoveIn(uint),
+}
+
+impl Access {
+    pub fn name(&self) -> String {
+        match *self {
+            Select => String::from_str("select"),
+            Push => String::from_str("push"),
+            Pick => String::from_str("pick"),
+            PickPush => String::from_str("pick+push"),
+            MoveOut(index) => format!("move to {}", index),
+            MoveIn(index) => format!("move from {}", index),
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Show)]
+pub struct Site {
+    /// Position in program coordinates.
+    pub position: (int, int),
+    pub access: Access,
+}
+
+pub struct StorageUsage {
+    pub index: uint,
+    /// Whether the storage is a queue rather than a stack.
+    pub queue: bool,
+    /// Sites in row then column order.
+    pub sites: Vec<Site>,
+}
+
+/// Values moved from one storage to another.
+pub struct Flow {
+    pub from: uint,
+    pub to: uint,
+    pub positions: Vec<(int, int)>,
+}
+
+pub struct Usage {
+    /// Every storage by index, used or not.
+    pub storages: Vec<StorageUsage>,
+    /// Flows ordered by source then target storage.
+    pub flows: Vec<Flow>,
+}
+
+impl Usage {
+    pub fn from_source(source: &Source) -> Usage {
+        Usage::from_graph(&Graph::from_source(source))
+    }
+
+    pub fn from_graph(graph: &Graph) -> Usage {
+        let mut sites: HashSet<(uint, Site)> = HashSet::new();
+        for (idx, selections) in selections(graph).iter().enumerate() {
+            let node = graph.nodes.get(idx);
+            let position = node.state.position();
+            for &selected in selections.iter() {
+                let access = match node.instruction.operation() {
+                    PushConstantOperation(_) | PushIntegerInputOperation | PushCharInputOperation => Some(Push),
+                    PopOperation | PrintIntegerOperation | PrintCharOperation | BranchOperation => Some(Pick),
+                    BinaryOperation(_) | CompareOperation | PushDuplicationOperation | SwapOperation => Some(PickPush),
+                    ChangeStorageOperation(index) => {
+                        sites.insert((index, Site { position: position, access: Select }));
+                        None
+                    }
+                    MoveToStorageOperation(index) => {
+                        sites.insert((index, Site { position: position, access: MoveIn(selected) }));
+                        Some(MoveOut(index))
+                    }
+                    _ => None,
+                };
+                match access {
+                    Some(access) => { sites.insert((selected, Site { position: position, access: access })); }
+                    None => { }
+                }
+            }
+        }
+
+        let mut sites: Vec<(uint, Site)> = sites.move_iter().collect();
+        sites.sort();
+        let mut usage = Usage {
+            storages: Vec::from_fn(28, |index| StorageUsage { index: index, queue: index == 21 || index == 27, sites: Vec::new() }),
+            flows: Vec::new(),
+        };
+        for &(index, site) in sites.iter() {
+            usage.storages.get_mut(index).sites.push(site);
+            match site.access {
+                MoveOut(to) => {
+                    let found = match usage.flows.mut_last() {
+                        Some(flow) if flow.from == index && flow.to == to => {
+                            flow.positions.push(site.position);
+                            true
+                        }
+                        _ => false,
+                    };
+                    if !found {
+                        usage.flows.push(Flow { from: index, to: to, positions: vec!(site.position) });
+                    }
+                }
+                _ => { }
+            }
+        }
+        usage
+    }
+
+    /// Indices of the storages with at least one site. Storage 0 is in use
+    /// whenever the program touches a storage before selecting another.
+    pub fn used(&self) -> Vec<uint> {
+        self.storages.iter().filter(|storage| !storage.sites.is_empty()).map(|storage| storage.index).collect()
+    }
+
+    pub fn write_report(&self, source: &Source, out: &mut Writer) -> IoResult<()> {
+        for storage in self.storages.iter().filter(|storage| !storage.sites.is_empty()) {
+            let name = storage_names[storage.index];
+            try!(writeln!(out, "storage {}{}{} ({})", storage.index, if name.is_empty() { "" } else { " " }, name,
+                          if storage.queue { "queue" } else { "stack" }));
+            for site in storage.sites.iter() {
+                let (row, col) = site.position;
+                let syllable = source.get(site.position).char().unwrap_or(' ');
+                try!(writeln!(out, "    {},{} {} {}", row, col, syllable, site.access.name()));
+            }
+        }
+        if !self.flows.is_empty() {
+            try!(writeln!(out, "flows"));
+        }
+        for flow in self.flows.iter() {
+            let positions: Vec<String> = flow.positions.iter().map(|&(row, col)| format!("{},{}", row, col)).collect();
+            try!(writeln!(out, "    {} -> {}: {}", flow.from, flow.to, positions.connect(" ")));
+        }
+        Ok(())
+    }
+}
+
+/// Storages which may be selected when each node of `graph` is executed, in
+/// increasing order.
+pub fn selections(graph: &Graph) -> Vec<Vec<uint>> {
+    let mut visited: HashSet<(uint, uint)> = HashSet::new();
+    let mut worklist: Vec<(uint, uint)> = Vec::new();
+    if graph.len() > 0 {
+        visited.insert((0, 0));
+        worklist.push((0, 0));
+    }
+
+    while !worklist.is_empty() {
+        let (idx, selected) = worklist.pop().unwrap();
+        let node = graph.nodes.get(idx);
+        let next_selected = match node.instruction.operation() {
+            ChangeStorageOperation(index) | MoveToStorageOperation(index) => index,
+            _ => selected,
+        };
+
+        // An operation lacking values reflects before changing the
+        // selection.
+        let mut successors = Vec::new();
+        match node.next {
+            Goto(next) => { successors.push((next, next_selected)); }
+            _ => { }
+        }
+        match node.reflected {
+            Some(Goto(next)) => { successors.push((next, selected)); }
+            _ => { }
+        }
+        for successor in successors.iter() {
+            if !visited.contains(successor) {
+                visited.insert(*successor);
+                worklist.push(*successor);
+            }
+        }
+    }
+
+    let mut result = Vec::from_elem(graph.len(), Vec::new());
+    for &(idx, selected) in visited.iter() {
+        result.get_mut(idx).push(selected);
+    }
+    for selections in result.mut_iter() {
+        selections.sort();
+    }
+    result
+}
@@ -0,0 +1,273 @@
+//! Terminal animation of a running program.
+//!
+//! Each frame redraws the part of the grid around the counter, with the
+//! current cell in reverse video, the selected storage beside the grid and
+//! the end of the program's output below it. Hangul syllables take two
+//! terminal columns; narrower characters are padded so that the columns line
+//! up.
+//!
+//! Keys are read from the terminal, not from the program's input:
+//!
+//! ```text
+//! space   pause or resume
+//! n       execute one instruction while paused
+//! + -     faster, slower
+//! q       quit
+//! ```
+
+use std::cmp;
+use std::comm::Empty;
+use std::io::{File, IoResult, ChanWriter, EndOfFile};
+use std::io::process::Command;
+use std::io::timer;
+
+use super::{Interpreter, Source, Up, Down, Left, Right, storage_names};
+
+static PANEL_WIDTH: uint = 24;
+static OUTPUT_LINES: uint = 3;
+static MAX_DELAY: u64 = 5000;
+
+pub struct Visualizer {
+    interpreter: Interpreter,
+    output: Receiver<Vec<u8>>,
+    printed: Vec<u8>,
+    /// Size of the viewport in cells.
+    rows: uint,
+    cols: uint,
+    /// Milliseconds between two frames.
+    delay: u64,
+    steps: u64,
+    halted: bool,
+}
+
+impl Visualizer {
+    /// Creates a visualizer which shows `rows` by `cols` cells of `source` at
+    /// a time. The program reads `input`; what it writes is shown below the
+    /// grid.
+    pub fn new(source: Source, input: Box<Buffer>, rows: uint, cols: uint, delay: u64) -> Visualizer {
+        let (tx, rx) = channel();
+        Visualizer {
+            interpreter: Interpreter::with_io(source, input, box ChanWriter::new(tx)),
+            output: rx,
+            printed: Vec::new(),
+            rows: rows,
+            cols: cols,
+            delay: delay,
+            steps: 0,
+            halted: false,
+        }
+    }
+
+    pub fn interpreter<'a>(&'a mut self) -> &'a mut Interpreter {
+        &mut self.interpreter
+    }
+
+    /// Executes one instruction of the program, going through the walls
+    /// without stopping. Returns true when the program halted.
+    pub fn step(&mut self) -> bool {
+        if self.halted {
+            return true;
+        }
+        loop {
+            self.halted = self.interpreter.step();
+            if self.halted || !self.interpreter.instruction().is_virtual() {
+                break;
+            }
+        }
+        self.steps += 1;
+        self.halted
+    }
+
+    /// Animates the program until it halts and `q` is pressed, or until
+    /// `keys` is closed while waiting for one.
+    pub fn run(&mut self, keys: &Receiver<u8>, out: &mut Writer) -> IoResult<()> {
+        let mut paused = false;
+        loop {
+            try!(self.render(out, paused));
+            try!(out.flush());
+            let key = if paused || self.halted {
+                match keys.recv_opt() {
+                    Ok(key) => Some(key as char),
+                    Err(()) => { return Ok(()); }
+                }
+            } else {
+                timer::sleep(self.delay);
+                keys.try_recv().ok().map(|key| key as char)
+            };
+            let mut step = !paused;
+            match key {
+                Some('q') => { return Ok(()); }
+                Some(' ') => {
+                    paused = !paused;
+                    step = false;
+                }
+                Some('n') => { step = true; }
+                Some('+') => { self.delay = cmp::max(self.delay / 2, 1); }
+                Some('-') => { self.delay = cmp::min(self.delay * 2, MAX_DELAY); }
+                _ => { }
+            }
+            if step {
+                self.step();
+            }
+        }
+    }
+
+    /// Draws one frame from the top of the screen.
+    pub fn render(&mut self, out: &mut Writer, paused: bool) -> IoResult<()> {
+        loop {
+            match self.output.try_recv() {
+                Ok(chunk) => { self.printed.push_all(chunk.as_slice()); }
+                Err(_) => { break; }
+            }
+        }
+
+        let (row, col) = self.interpreter.counter();
+        let instruction = self.interpreter.instruction();
+        let arrow = match self.interpreter.direction() {
+            Down => '↓',
+            Up => '↑',
+            Right => '→',
+            Left => '←',
+        };
+        let state = if self.halted { "halted" } else if paused { "paused" } else { "running" };
+        try!(out.write_str("\x1b[H\x1b[2J"));
+        try!(write!(out, "step {}  ({}, {}) {}  {}", self.steps, row, col, arrow, state));
+        match instruction.char() {
+            Some(c) => { try!(writeln!(out, "  {} {}", c, instruction.mnemonic())); }
+            None => { try!(writeln!(out, "")); }
+        }
+
+        let source = self.interpreter.source();
+        let first_row = origin(row, self.rows, source.height());
+        let widest = range(0, source.height()).map(|r| source.row_len(r)).max().unwrap_or(0);
+        let first_col = origin(col, self.cols, widest);
+        let panel = self.panel();
+        for i in range(0, self.rows) {
+            let r = first_row + i;
+            for c in range(first_col, first_col + self.cols) {
+                if r >= source.height() || c >= source.row_len(r) {
+                    try!(out.write_str("  "));
+                    continue;
+                }
+                let ch = match source.get((r as int, c as int)).char() {
+                    Some(ch) if !ch.is_control() => ch,
+                    _ => ' ',
+                };
+                let padding = if width(ch) == 1 { " " } else { "" };
+                if (r as int, c as int) == (row, col) {
+                    try!(write!(out, "\x1b[7m{}{}\x1b[0m", ch, padding));
+                } else {
+                    try!(write!(out, "{}{}", ch, padding));
+                }
+            }
+            match panel.as_slice().get(i) {
+                Some(line) => { try!(writeln!(out, "  {}", line)); }
+                None => { try!(writeln!(out, "")); }
+            }
+        }
+
+        try!(writeln!(out, "{}", "─".repeat(self.cols * 2 + 2 + PANEL_WIDTH)));
+        let printed = String::from_utf8(self.printed.clone()).ok().unwrap_or(String::new());
+        let lines: Vec<&str> = printed.as_slice().lines().collect();
+        let first = if lines.len() > OUTPUT_LINES { lines.len() - OUTPUT_LINES } else { 0 };
+        for line in lines.slice_from(first).iter() {
+            try!(writeln!(out, "{}", line));
+        }
+        Ok(())
+    }
+
+    /// Lines beside the grid: the selected storage, then its values in the
+    /// order they will be picked.
+    fn panel(&self) -> Vec<String> {
+        let index = self.interpreter.storage_index();
+        let kind = if index == 21 || index == 27 { "queue" } else { "stack" };
+        let mut values = self.interpreter.storage_at(index).values();
+        if kind == "stack" {
+            values.reverse();
+        }
+        let mut lines = vec!(format!("storage {} {} {}", index, storage_names[index], kind));
+        let room = if self.rows > 1 { self.rows - 1 } else { 0 };
+        for (i, v) in values.iter().enumerate() {
+            if i + 1 == room && values.len() > room {
+                lines.push(format!("… {} more", values.len() - i));
+                break;
+            }
+            lines.push(format!("{}", v));
+        }
+        lines
+    }
+}
+
+/// First row or column of a viewport of `size` showing `position` of `total`,
+/// keeping the position in the middle when possible.
+fn origin(position: int, size: uint, total: uint) -> uint {
+    let position = cmp::max(position, 0) as uint;
+    if total <= size || position < size / 2 {
+        0
+    } else {
+        cmp::min(position - size / 2, total - size)
+    }
+}
+
+/// Terminal columns a character takes.
+pub fn width(c: char) -> uint {
+    match c as u32 {
+        0x1100..0x115f | 0x2e80..0xa4cf | 0xac00..0xd7a3 | 0xf900..0xfaff |
+        0xfe30..0xfe4f | 0xff00..0xff60 | 0xffe0..0xffe6 => 2,
+        _ => 1,
+    }
+}
+
+/// Keys typed on the controlling terminal. The terminal is set to pass them
+/// through without waiting for a newline or echoing them, and restored when
+/// this is dropped.
+pub struct Keys {
+    keys: Receiver<u8>,
+    #[allow(dead_code)]
+    stop: Sender<()>,
+}
+
+impl Keys {
+    pub fn open() -> IoResult<Keys> {
+        let mut tty = try!(File::open(&Path::new("/dev/tty")));
+        // Reads give up after a tenth of a second, so that the task notices
+        // when it is no longer needed.
+        try!(stty(&["-icanon", "-echo", "min", "0", "time", "1"]));
+        let (tx, rx) = channel();
+        let (stop, stopped) = channel::<()>();
+        spawn(proc() {
+            loop {
+                match stopped.try_recv() {
+                    Err(Empty) => { }
+                    _ => { break; }
+                }
+                match tty.read_byte() {
+                    Ok(key) => {
+                        if tx.send_opt(key).is_err() {
+                            break;
+                        }
+                    }
+                    Err(ref e) if e.kind == EndOfFile => { }
+                    Err(_) => { break; }
+                }
+            }
+        });
+        Ok(Keys { keys: rx, stop: stop })
+    }
+
+    pub fn receiver<'a>(&'a self) -> &'a Receiver<u8> {
+        &self.keys
+    }
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        let _ = stty(&["icanon", "echo"]);
+    }
+}
+
+fn stty(args: &[&str]) -> IoResult<()> {
+    let command = format!("stty {} < /dev/tty", args.connect(" "));
+    try!(Command::new("sh").arg("-c").arg(command).status());
+    Ok(())
+}
@@ -0,0 +1,239 @@
+//! Minimal x86-64 machine code assembler.
+//!
+//! Only the instructions the code generators need are provided. Memory
+//! operands are always `[base + disp32]`; jumps and calls to labels are
+//! `rel32` and resolved by `finish`.
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Register {
+    RAX = 0, RCX, RDX, RBX, RSP, RBP, RSI, RDI,
+    R8, R9, R10, R11, R12, R13, R14, R15,
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub enum Condition {
+    Below = 0x2,
+    AboveEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    BelowEqual = 0x6,
+    Above = 0x7,
+    Sign = 0x8,
+    NotSign = 0x9,
+    Less = 0xc,
+    GreaterEqual = 0xd,
+    LessEqual = 0xe,
+    Greater = 0xf,
+}
+
+#[deriving(Clone, PartialEq, Show)]
+pub struct Label(uint);
+
+pub struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<uint>>,
+    /// Offsets of rel32 fields and the labels they refer to.
+    fixups: Vec<(uint, uint)>,
+}
+
+fn low(r: Register) -> u8 { (r as u8) & 7 }
+fn high(r: Register) -> u8 { (r as u8) >> 3 }
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler { code: Vec::new(), labels: Vec::new(), fixups: Vec::new() }
+    }
+
+    pub fn len(&self) -> uint {
+        self.code.len()
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    pub fn bind(&mut self, label: Label) {
+        let Label(idx) = label;
+        assert!(self.labels.get(idx).is_none());
+        *self.labels.get_mut(idx) = Some(self.code.len());
+    }
+
+    /// Offset of a bound label from the beginning of the code.
+    pub fn offset(&self, label: Label) -> Option<uint> {
+        let Label(idx) = label;
+        *self.labels.get(idx)
+    }
+
+    /// Resolves every label reference and returns the machine code.
+    pub fn finish(self) -> Vec<u8> {
+        let mut code = self.code;
+        for &(at, label) in self.fixups.iter() {
+            let target = match *self.labels.get(label) {
+                Some(target) => target,
+                None => fail!("unbound label"),
+            };
+            let rel = (target as i64 - (at as i64 + 4)) as i32;
+            for i in range(0u, 4) {
+                *code.get_mut(at + i) = (rel >> (8 * i)) as u8;
+            }
+        }
+        code
+    }
+
+    pub fn emit(&mut self, bytes: &[u8]) {
+        self.code.push_all(bytes);
+    }
+
+    pub fn emit_u32(&mut self, v: u32) {
+        for i in range(0u, 4) {
+            self.code.push((v >> (8 * i)) as u8);
+        }
+    }
+
+    pub fn emit_u64(&mut self, v: u64) {
+        for i in range(0u, 8) {
+            self.code.push((v >> (8 * i)) as u8);
+        }
+    }
+
+    fn rel32(&mut self, label: Label) {
+        let Label(idx) = label;
+        self.fixups.push((self.code.len(), idx));
+        self.emit_u32(0);
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, base: u8) {
+        self.code.push(0x40 | (if w { 8 } else { 0 }) | (reg << 2) | base);
+    }
+
+    /// `op reg, rm` with a register operand in the r/m field.
+    fn rr(&mut self, opcode: &[u8], reg: Register, rm: Register) {
+        self.rex(true, high(reg), high(rm));
+        self.emit(opcode);
+        self.code.push(0xc0 | (low(reg) << 3) | low(rm));
+    }
+
+    /// `op reg, [base + disp]`.
+    fn rm(&mut self, w: bool, opcode: &[u8], reg: Register, base: Register, disp: i32) {
+        self.rex(w, high(reg), high(base));
+        self.emit(opcode);
+        self.code.push(0x80 | (low(reg) << 3) | low(base));
+        if low(base) == 4 {
+            self.code.push(0x24);
+        }
+        self.emit_u32(disp as u32);
+    }
+
+    /// Group 1 arithmetic with a 32-bit immediate: `op reg, imm`.
+    fn ri(&mut self, extension: u8, reg: Register, imm: i32) {
+        self.rex(true, 0, high(reg));
+        self.code.push(0x81);
+        self.code.push(0xc0 | (extension << 3) | low(reg));
+        self.emit_u32(imm as u32);
+    }
+
+    pub fn mov_imm(&mut self, reg: Register, imm: u64) {
+        self.rex(true, 0, high(reg));
+        self.code.push(0xb8 + low(reg));
+        self.emit_u64(imm);
+    }
+
+    pub fn mov(&mut self, dst: Register, src: Register) { self.rr(&[0x89], src, dst) }
+    pub fn add(&mut self, dst: Register, src: Register) { self.rr(&[0x01], src, dst) }
+    pub fn sub(&mut self, dst: Register, src: Register) { self.rr(&[0x29], src, dst) }
+    pub fn cmp(&mut self, dst: Register, src: Register) { self.rr(&[0x39], src, dst) }
+    pub fn test(&mut self, dst: Register, src: Register) { self.rr(&[0x85], src, dst) }
+    pub fn and(&mut self, dst: Register, src: Register) { self.rr(&[0x21], src, dst) }
+    pub fn xor(&mut self, dst: Register, src: Register) { self.rr(&[0x31], src, dst) }
+    pub fn imul(&mut self, dst: Register, src: Register) { self.rr(&[0x0f, 0xaf], dst, src) }
+
+    pub fn add_imm(&mut self, reg: Register, imm: i32) { self.ri(0, reg, imm) }
+    pub fn and_imm(&mut self, reg: Register, imm: i32) { self.ri(4, reg, imm) }
+    pub fn sub_imm(&mut self, reg: Register, imm: i32) { self.ri(5, reg, imm) }
+    pub fn cmp_imm(&mut self, reg: Register, imm: i32) { self.ri(7, reg, imm) }
+
+    /// `mov dst, qword [base + disp]`
+    pub fn load(&mut self, dst: Register, base: Register, disp: i32) { self.rm(true, &[0x8b], dst, base, disp) }
+    /// `mov qword [base + disp], src`
+    pub fn store(&mut self, base: Register, disp: i32, src: Register) { self.rm(true, &[0x89], src, base, disp) }
+    /// `movzx dst, byte [base + disp]`
+    pub fn load_byte(&mut self, dst: Register, base: Register, disp: i32) { self.rm(true, &[0x0f, 0xb6], dst, base, disp) }
+    /// `mov byte [base + disp], src`
+    pub fn store_byte(&mut self, base: Register, disp: i32, src: Register) { self.rm(false, &[0x88], src, base, disp) }
+    /// `lea dst, [base + disp]`
+    pub fn lea(&mut self, dst: Register, base: Register, disp: i32) { self.rm(true, &[0x8d], dst, base, disp) }
+
+    /// `shl reg, imm` when `imm` is positive, `sar reg, -imm` otherwise.
+    pub fn shift(&mut self, reg: Register, imm: i8) {
+        self.rex(true, 0, high(reg));
+        self.code.push(0xc1);
+        if imm >= 0 {
+            self.code.push(0xe0 | low(reg));
+            self.code.push(imm as u8);
+        } else {
+            self.code.push(0xf8 | low(reg));
+            self.code.push(-imm as u8);
+        }
+    }
+
+    pub fn neg(&mut self, reg: Register) {
+        self.rex(true, 0, high(reg));
+        self.code.push(0xf7);
+        self.code.push(0xd8 | low(reg));
+    }
+
+    /// `cqo; idiv reg`: quotient in rax, remainder in rdx.
+    pub fn idiv(&mut self, reg: Register) {
+        self.emit(&[0x48, 0x99]);
+        self.rex(true, 0, high(reg));
+        self.code.push(0xf7);
+        self.code.push(0xf8 | low(reg));
+    }
+
+    pub fn push(&mut self, reg: Register) {
+        if high(reg) != 0 {
+            self.code.push(0x41);
+        }
+        self.code.push(0x50 + low(reg));
+    }
+
+    pub fn pop(&mut self, reg: Register) {
+        if high(reg) != 0 {
+            self.code.push(0x41);
+        }
+        self.code.push(0x58 + low(reg));
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.rel32(label);
+    }
+
+    pub fn jcc(&mut self, condition: Condition, label: Label) {
+        self.code.push(0x0f);
+        self.code.push(0x80 + condition as u8);
+        self.rel32(label);
+    }
+
+    pub fn call(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.rel32(label);
+    }
+
+    pub fn call_reg(&mut self, reg: Register) {
+        if high(reg) != 0 {
+            self.code.push(0x41);
+        }
+        self.code.push(0xff);
+        self.code.push(0xd0 | low(reg));
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+    }
+
+    pub fn syscall(&mut self) {
+        self.emit(&[0x0f, 0x05]);
+    }
+}